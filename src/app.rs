@@ -1,4 +1,4 @@
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 pub fn parse_args() -> ArgMatches<'static> {
     App::new("TANA - the Elasticsearch Data Cruncher")
@@ -30,10 +30,199 @@ pub fn parse_args() -> ArgMatches<'static> {
                  .long("gzip")
                  .takes_value(false)
                  .help("If supplied, compress output using gzip"))
+        .arg(Arg::with_name("zstd")
+                 .long("zstd")
+                 .takes_value(false)
+                 .conflicts_with("gzip")
+                 .help("If supplied, compress output using zstd"))
+        .arg(Arg::with_name("zstd_level")
+                 .long("zstd-level")
+                 .takes_value(true)
+                 .help("With '--zstd', the compression level from 1 to 22. Defaults to 3."))
+        .arg(Arg::with_name("from")
+                 .long("from")
+                 .takes_value(true)
+                 .help("Read documents from an existing dump instead of Elasticsearch: a manifest or dump file, s3:<manifest key> in '--bucket', or '-' for NDJSON on stdin."))
+        .arg(Arg::with_name("ca_cert")
+                .long("ca-cert")
+                .takes_value(true)
+                .help("A PEM CA certificate to trust for an https:// ES_HOST, e.g. the cluster's own CA."))
+        .arg(Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .help("Seconds to wait for each request to Elasticsearch. Defaults to 10."))
         .arg(Arg::with_name("concurrency")
                 .short("con")
                 .long("concurrency")
                 .takes_value(true)
-                .help("The number of scrollers to create. Each scroller can make its own concurrent requests."))
-	.get_matches()
+                .help("The number of scrollers to create. Each scroller can make its own concurrent requests. [auto] picks one per primary shard."))
+        .arg(Arg::with_name("max_concurrency")
+                .long("max-concurrency")
+                .takes_value(true)
+                .help("The most scrollers '--concurrency auto' may create. Defaults to 16."))
+        .arg(Arg::with_name("output_dir")
+                .long("output-dir")
+                .takes_value(true)
+                .help("Where to write the dump and its manifest. Defaults to the current directory."))
+        .arg(Arg::with_name("work_dir")
+                .long("work-dir")
+                .takes_value(true)
+                .help("Where to keep temporary slice files during a run. Defaults to the output directory."))
+        .arg(Arg::with_name("part_documents")
+                .long("part-documents")
+                .takes_value(true)
+                .help("Split the output into numbered parts of at most this many documents each."))
+        .arg(Arg::with_name("part_size")
+                .long("part-size")
+                .takes_value(true)
+                .help("Split the output into numbered parts of at most this many bytes before compression. Accepts K, M and G suffixes, e.g. [512M]. Needed for '--dest s3', which uploads each part from memory."))
+        .arg(Arg::with_name("partition_by")
+                .long("partition-by")
+                .takes_value(true)
+                .possible_values(&["day", "hour", "field"])
+                .help("Split the output into a directory per day or hour of '--partition-field', or per value of it."))
+        .arg(Arg::with_name("partition_field")
+                .long("partition-field")
+                .takes_value(true)
+                .help("The field to partition on. Defaults to [@timestamp] for day and hour."))
+        .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["bulk", "jsonl", "parquet", "avro", "csv", "tsv"])
+                .help("Write action and source line pairs ready for _bulk, one JSON document per line, Parquet or Avro typed from the index mapping, or CSV or TSV rows of '--fields'. Defaults to [bulk]."))
+        .arg(Arg::with_name("id_key")
+                .long("id-key")
+                .takes_value(true)
+                .help("With '--format jsonl', add each document's _id under this key."))
+        .arg(Arg::with_name("index_key")
+                .long("index-key")
+                .takes_value(true)
+                .help("With '--format jsonl', add each document's _index under this key."))
+        .arg(Arg::with_name("parquet_compression")
+                .long("parquet-compression")
+                .takes_value(true)
+                .possible_values(&["snappy", "zstd"])
+                .help("With '--format parquet', how to compress the column pages. Defaults to [snappy]."))
+        .arg(Arg::with_name("row_group_size")
+                .long("row-group-size")
+                .takes_value(true)
+                .help("With '--format parquet', the most rows in a row group. Defaults to [100000]."))
+        .arg(Arg::with_name("avro_codec")
+                .long("avro-codec")
+                .takes_value(true)
+                .possible_values(&["deflate", "zstd"])
+                .help("With '--format avro', how to compress each block. Defaults to [deflate]."))
+        .arg(Arg::with_name("avro_nullable")
+                .long("avro-nullable")
+                .takes_value(false)
+                .help("With '--format avro', make every field a union with null, so documents missing fields still fit the schema."))
+        .arg(Arg::with_name("fields")
+                .long("fields")
+                .takes_value(true)
+                .help("With '--format csv' or 'tsv', the columns in order, comma separated. Dotted paths reach into objects, _index and _id are the document's. E.g. [_id,@timestamp,user.name]."))
+        .arg(Arg::with_name("no_header")
+                .long("no-header")
+                .takes_value(false)
+                .help("With '--format csv' or 'tsv', leave out the header row."))
+        .arg(Arg::with_name("quote")
+                .long("quote")
+                .takes_value(true)
+                .possible_values(&["necessary", "always", "non-numeric", "never"])
+                .help("With '--format csv' or 'tsv', which cells to quote. Defaults to [necessary]."))
+        .arg(Arg::with_name("null")
+                .long("null")
+                .takes_value(true)
+                .help("With '--format csv' or 'tsv', what to write for a missing or null field. Defaults to an empty cell."))
+        .arg(Arg::with_name("arrays")
+                .long("arrays")
+                .takes_value(true)
+                .possible_values(&["join", "first", "json"])
+                .help("With '--format csv' or 'tsv', write arrays joined by '--separator', just their first element, or as JSON. Defaults to [join]."))
+        .arg(Arg::with_name("objects")
+                .long("objects")
+                .takes_value(true)
+                .possible_values(&["json", "pairs"])
+                .help("With '--format csv' or 'tsv', write objects as JSON, or as key=value pairs joined by '--separator'. Defaults to [json]."))
+        .arg(Arg::with_name("separator")
+                .long("separator")
+                .takes_value(true)
+                .help("What joins array elements and object pairs within a cell. Defaults to [;]."))
+        .arg(Arg::with_name("allow_partial")
+                .long("allow-partial")
+                .takes_value(false)
+                .help("Keep and upload the output even if some slices failed. Still exits with code 2. With '--from', also read a partial dump."))
+        .arg(Arg::with_name("query")
+                .short("q")
+                .long("query")
+                .takes_value(true)
+                .help("Only dump documents matching this Elasticsearch query, as JSON. E.g. [{\"match_all\": {}}]."))
+        .arg(Arg::with_name("source_includes")
+                .long("source-includes")
+                .takes_value(true)
+                .help("Only dump these fields of each document, comma separated. Wildcards work, e.g. [@timestamp,host.*]."))
+        .arg(Arg::with_name("source_excludes")
+                .long("source-excludes")
+                .takes_value(true)
+                .help("Leave these fields out of each document, comma separated. Wildcards work, e.g. [message,winlog.event_data]."))
+        .arg(Arg::with_name("transform")
+                .long("transform")
+                .takes_value(true)
+                .help("A JSON file of steps to rename, remove, set, copy, lowercase, date_format or drop fields of each document before it's written."))
+        .arg(Arg::with_name("script")
+                .long("script")
+                .takes_value(true)
+                .help("A Rhai script run on each document as `hit`. It can change it, return false to drop it, or return a name to write it to that output."))
+        .arg(Arg::with_name("script_timeout")
+                .long("script-timeout")
+                .takes_value(true)
+                .help("With '--script', the most milliseconds it may take on one document. Defaults to 50."))
+        .arg(Arg::with_name("redact")
+                .long("redact")
+                .takes_value(true)
+                .help("A JSON file of rules to mask, remove, hash or truncate_ip fields chosen by path or regex, before anything is written."))
+        .arg(Arg::with_name("hmac_key_file")
+                .long("hmac-key-file")
+                .takes_value(true)
+                .help("With '--redact', the file holding the secret key for hash rules. Keep it to hash the same values the same way next time."))
+        .arg(Arg::with_name("verify_ids")
+                .long("verify-ids")
+                .takes_value(false)
+                .help("After dumping, check that no document id was written more than once."))
+        .arg(Arg::with_name("page_size")
+                .long("page-size")
+                .takes_value(true)
+                .help("The number of documents each scroller asks for per page, at most 10000. Defaults to 1000."))
+        .arg(Arg::with_name("keep_alive")
+                .long("keep-alive")
+                .takes_value(true)
+                .help("How long Elasticsearch keeps each scroll alive between pages. Defaults to [1m]."))
+        .arg(Arg::with_name("adaptive")
+                .long("adaptive-page-size")
+                .takes_value(false)
+                .help("Shrink pages after timeouts or circuit breaker errors and grow them while they're quick. Needs a point in time (Elasticsearch 7.12+)."))
+        .arg(Arg::with_name("shard_routing")
+                .long("shard-routing")
+                .takes_value(false)
+                .help("Pin each scroller to whole shards and read them from a node holding them, instead of through ES_HOST. Needs a single index, not an alias or pattern over several."))
+        .arg(Arg::with_name("sniff")
+                .long("sniff")
+                .takes_value(false)
+                .help("Discover the cluster's other nodes from ES_HOST and spread requests across all of them."))
+        .subcommand(SubCommand::with_name("verify")
+                .about("Check an existing dump offline against its manifest, without a cluster.")
+                .arg(Arg::with_name("path")
+                        .required(true)
+                        .index(1)
+                        .help("The dump's manifest, or a bare dump file. With '--bucket', the manifest's S3 key."))
+                .arg(Arg::with_name("bucket")
+                        .short("b")
+                        .long("bucket")
+                        .takes_value(true)
+                        .help("Read the dump from this S3 bucket instead of disk."))
+                .arg(Arg::with_name("region")
+                        .short("r")
+                        .long("region")
+                        .takes_value(true)
+                        .help("The AWS region where the S3 bucket resides. E.g. [eu-west-2].")))
+        .get_matches()
 }
@@ -0,0 +1,730 @@
+use crate::dumper::columnar::{expand_dots, mapped_properties, number};
+use crate::dumper::partition;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, Error as IOError, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+const MAGIC: &[u8; 4] = b"Obj\x01";
+// Records per block, readers can split a file at any block
+const BLOCK_RECORDS: usize = 4096;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AvroCodec {
+    Deflate,
+    Zstd,
+}
+
+impl AvroCodec {
+    pub fn name(&self) -> &'static str {
+        // As the avro.codec metadata spells it
+        match self {
+            AvroCodec::Deflate => "deflate",
+            AvroCodec::Zstd => "zstandard",
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, IOError> {
+        match self {
+            AvroCodec::Deflate => {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            AvroCodec::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AvroType {
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    String,
+    // Longs since the epoch, in milliseconds or nanoseconds
+    TimestampMillis,
+    TimestampNanos,
+    Record {
+        name: String,
+        fields: Vec<AvroField>,
+    },
+    Array(Box<AvroType>),
+    // A union of null and the type
+    Nullable(Box<AvroType>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvroField {
+    // Avro names are [A-Za-z_][A-Za-z0-9_]*, so @timestamp is written as _timestamp
+    pub name: String,
+    // The key in _source
+    pub source: String,
+    pub data_type: AvroType,
+}
+
+pub fn schema_from_mapping(
+    index: &str,
+    mapping: &JsonValue,
+    lists: &HashSet<String>,
+    nullable: bool,
+) -> AvroType {
+    let name = avro_name(index);
+    let mut names = HashSet::new();
+    // The document's identity first, documents read without an id have a null one
+    let mut fields: Vec<AvroField> = ["_index", "_id"]
+        .iter()
+        .map(|meta| AvroField {
+            name: unique_name(meta, &mut names),
+            source: meta.to_string(),
+            data_type: or_null(AvroType::String, *meta == "_id"),
+        })
+        .collect();
+    let properties = mapped_properties(mapping);
+    fields.extend(record_fields(
+        &properties,
+        "",
+        &name,
+        lists,
+        nullable,
+        &mut names,
+    ));
+    AvroType::Record { name, fields }
+}
+
+fn record_fields(
+    properties: &Map<String, JsonValue>,
+    prefix: &str,
+    record: &str,
+    lists: &HashSet<String>,
+    nullable: bool,
+    names: &mut HashSet<String>,
+) -> Vec<AvroField> {
+    properties
+        .iter()
+        .filter_map(|(key, mapping)| {
+            let path = format!("{}{}", prefix, key);
+            let nested = format!("{}_{}", record, avro_name(key));
+            let data_type = field_type(mapping, &path, &nested, lists, nullable)?;
+            // Any field can hold an array, the ones that did are arrays
+            let data_type = if lists.contains(&path) && !matches!(data_type, AvroType::Array(_)) {
+                AvroType::Array(Box::new(or_null(data_type, nullable)))
+            } else {
+                data_type
+            };
+            Some(AvroField {
+                name: unique_name(key, names),
+                source: key.clone(),
+                data_type: or_null(data_type, nullable),
+            })
+        })
+        .collect()
+}
+
+fn field_type(
+    mapping: &JsonValue,
+    path: &str,
+    record: &str,
+    lists: &HashSet<String>,
+    nullable: bool,
+) -> Option<AvroType> {
+    if let Some(JsonValue::Object(properties)) = mapping.get("properties") {
+        let prefix = format!("{}.", path);
+        let fields = record_fields(
+            properties,
+            &prefix,
+            record,
+            lists,
+            nullable,
+            &mut HashSet::new(),
+        );
+        if fields.is_empty() {
+            return None;
+        }
+        let data_type = AvroType::Record {
+            name: record.to_string(),
+            fields,
+        };
+        return Some(match mapping["type"].as_str() {
+            Some("nested") => AvroType::Array(Box::new(or_null(data_type, nullable))),
+            _ => data_type,
+        });
+    }
+
+    Some(match mapping["type"].as_str().unwrap_or("object") {
+        // An unsigned_long past i64::MAX doesn't fit and goes to the dead letters
+        "long" | "unsigned_long" => AvroType::Long,
+        "integer" | "short" | "byte" => AvroType::Int,
+        "double" | "scaled_float" => AvroType::Double,
+        "float" | "half_float" => AvroType::Float,
+        "boolean" => AvroType::Boolean,
+        "date" => AvroType::TimestampMillis,
+        "date_nanos" => AvroType::TimestampNanos,
+        // Aliases have no value in _source
+        "alias" => return None,
+        // keyword, text, ip and anything without a natural Avro type, as JSON text if need be
+        _ => AvroType::String,
+    })
+}
+
+fn or_null(data_type: AvroType, nullable: bool) -> AvroType {
+    if nullable {
+        AvroType::Nullable(Box::new(data_type))
+    } else {
+        data_type
+    }
+}
+
+fn avro_name(name: &str) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn unique_name(key: &str, names: &mut HashSet<String>) -> String {
+    // web.host and web-host would both be web_host
+    let name = avro_name(key);
+    let mut candidate = name.clone();
+    let mut n = 1;
+    while !names.insert(candidate.clone()) {
+        n += 1;
+        candidate = format!("{}_{}", name, n);
+    }
+    candidate
+}
+
+impl AvroType {
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            AvroType::Boolean => json!("boolean"),
+            AvroType::Int => json!("int"),
+            AvroType::Long => json!("long"),
+            AvroType::Float => json!("float"),
+            AvroType::Double => json!("double"),
+            AvroType::String => json!("string"),
+            AvroType::TimestampMillis => {
+                json!({ "type": "long", "logicalType": "timestamp-millis" })
+            }
+            AvroType::TimestampNanos => json!({ "type": "long", "logicalType": "timestamp-nanos" }),
+            AvroType::Record { name, fields } => {
+                let fields: Vec<JsonValue> = fields
+                    .iter()
+                    .map(|field| {
+                        let mut json =
+                            json!({ "name": field.name, "type": field.data_type.to_json() });
+                        if let AvroType::Nullable(_) = field.data_type {
+                            json["default"] = JsonValue::Null;
+                        }
+                        // Where the field came from when its name had to change
+                        if field.name != field.source {
+                            json["source"] = json!(field.source);
+                        }
+                        json
+                    })
+                    .collect();
+                json!({ "type": "record", "name": name, "fields": fields })
+            }
+            AvroType::Array(items) => json!({ "type": "array", "items": items.to_json() }),
+            AvroType::Nullable(data_type) => json!(["null", data_type.to_json()]),
+        }
+    }
+
+    fn from_json(json: &JsonValue) -> Option<AvroType> {
+        // Only what to_json writes, enough to read our own files back
+        match json {
+            JsonValue::String(name) => Some(match name.as_str() {
+                "boolean" => AvroType::Boolean,
+                "int" => AvroType::Int,
+                "long" => AvroType::Long,
+                "float" => AvroType::Float,
+                "double" => AvroType::Double,
+                "string" => AvroType::String,
+                _ => return None,
+            }),
+            JsonValue::Array(branches) if branches.len() == 2 && branches[0] == "null" => Some(
+                AvroType::Nullable(Box::new(AvroType::from_json(&branches[1])?)),
+            ),
+            JsonValue::Object(object) => match object.get("type")?.as_str()? {
+                "record" => {
+                    let fields = object.get("fields")?.as_array()?.iter().map(|field| {
+                        let name = field["name"].as_str()?.to_string();
+                        Some(AvroField {
+                            source: field["source"].as_str().unwrap_or(&name).to_string(),
+                            data_type: AvroType::from_json(&field["type"])?,
+                            name,
+                        })
+                    });
+                    Some(AvroType::Record {
+                        name: object.get("name")?.as_str()?.to_string(),
+                        fields: fields.collect::<Option<Vec<AvroField>>>()?,
+                    })
+                }
+                "array" => Some(AvroType::Array(Box::new(AvroType::from_json(
+                    object.get("items")?,
+                )?))),
+                "long" => Some(match object.get("logicalType").and_then(|t| t.as_str()) {
+                    Some("timestamp-millis") => AvroType::TimestampMillis,
+                    Some("timestamp-nanos") => AvroType::TimestampNanos,
+                    _ => AvroType::Long,
+                }),
+                name => AvroType::from_json(&json!(name)),
+            },
+            _ => None,
+        }
+    }
+
+    fn encode(
+        &self,
+        value: Option<&JsonValue>,
+        path: &str,
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        // Unlike Parquet nothing is bent to fit, the reason is kept for the dead letters
+        let value = value.filter(|value| !value.is_null());
+        if let AvroType::Nullable(data_type) = self {
+            match value {
+                None => write_long(out, 0),
+                Some(value) => {
+                    write_long(out, 1);
+                    data_type.encode(Some(value), path, out)?;
+                }
+            }
+            return Ok(());
+        }
+        let value = value.ok_or_else(|| format!("{} is missing or null", path))?;
+        let unfit = || format!("{} is not a {}: {}", path, self.to_json(), value);
+
+        match (self, value) {
+            (AvroType::Array(items), JsonValue::Array(values)) => {
+                if !values.is_empty() {
+                    write_long(out, values.len() as i64);
+                    for value in values {
+                        items.encode(Some(value), path, out)?;
+                    }
+                }
+                write_long(out, 0);
+            }
+            (AvroType::Array(items), value) => {
+                write_long(out, 1);
+                items.encode(Some(value), path, out)?;
+                write_long(out, 0);
+            }
+            (_, JsonValue::Array(values)) if values.len() == 1 => {
+                self.encode(values.first(), path, out)?
+            }
+            (AvroType::Record { fields, .. }, JsonValue::Object(object)) => {
+                for field in fields {
+                    let path = match path {
+                        "" => field.source.clone(),
+                        path => format!("{}.{}", path, field.source),
+                    };
+                    field
+                        .data_type
+                        .encode(object.get(&field.source), &path, out)?;
+                }
+            }
+            (AvroType::String, JsonValue::String(text)) => write_bytes(out, text.as_bytes()),
+            (AvroType::String, value) => write_bytes(out, value.to_string().as_bytes()),
+            (AvroType::Boolean, JsonValue::Bool(value)) => out.push(*value as u8),
+            (AvroType::Boolean, JsonValue::String(text)) if text == "true" || text == "false" => {
+                out.push((text == "true") as u8)
+            }
+            (AvroType::Int, value) => {
+                let n = integer(value)
+                    .and_then(|n| i32::try_from(n).ok())
+                    .ok_or_else(unfit)?;
+                write_long(out, n as i64);
+            }
+            (AvroType::Long, value) => write_long(out, integer(value).ok_or_else(unfit)?),
+            (AvroType::Float, value) => {
+                let n = number(value).and_then(|n| n.as_f64()).ok_or_else(unfit)?;
+                out.extend_from_slice(&(n as f32).to_le_bytes());
+            }
+            (AvroType::Double, value) => {
+                let n = number(value).and_then(|n| n.as_f64()).ok_or_else(unfit)?;
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            (AvroType::TimestampMillis, value) => {
+                let at = partition::timestamp(value).ok_or_else(unfit)?;
+                write_long(out, at.timestamp_millis());
+            }
+            (AvroType::TimestampNanos, value) => {
+                let at = partition::timestamp(value)
+                    .and_then(|at| at.timestamp_nanos_opt())
+                    .ok_or_else(unfit)?;
+                write_long(out, at);
+            }
+            _ => return Err(unfit()),
+        }
+        Ok(())
+    }
+
+    fn skip(&self, input: &mut &[u8]) -> Option<()> {
+        match self {
+            AvroType::Boolean => take(input, 1).map(|_| ()),
+            AvroType::Int
+            | AvroType::Long
+            | AvroType::TimestampMillis
+            | AvroType::TimestampNanos => read_long(input).map(|_| ()),
+            AvroType::Float => take(input, 4).map(|_| ()),
+            AvroType::Double => take(input, 8).map(|_| ()),
+            AvroType::String => read_bytes(input).map(|_| ()),
+            AvroType::Record { fields, .. } => fields
+                .iter()
+                .try_for_each(|field| field.data_type.skip(input)),
+            AvroType::Array(items) => loop {
+                let mut count = read_long(input)?;
+                if count == 0 {
+                    return Some(());
+                }
+                // A negative count is followed by the block's size in bytes
+                if count < 0 {
+                    read_long(input)?;
+                    count = -count;
+                }
+                for _ in 0..count {
+                    items.skip(input)?;
+                }
+            },
+            AvroType::Nullable(data_type) => match read_long(input)? {
+                0 => Some(()),
+                1 => data_type.skip(input),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn integer(value: &JsonValue) -> Option<i64> {
+    // Elasticsearch truncates the fraction of a whole-number field, so do we
+    let n = number(value)?;
+    n.as_i64().or_else(|| {
+        n.as_f64()
+            .filter(|f| f.is_finite() && f.abs() < i64::MAX as f64)
+            .map(|f| f.trunc() as i64)
+    })
+}
+
+fn write_long(out: &mut Vec<u8>, n: i64) {
+    // Zig-zag, then 7 bits at a time
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_long(out, bytes.len() as i64);
+    out.extend_from_slice(bytes);
+}
+
+fn read_long(input: &mut &[u8]) -> Option<i64> {
+    let mut n: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(input, 1)?[0];
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some((n >> 1) as i64 ^ -((n & 1) as i64));
+        }
+    }
+    None
+}
+
+fn read_bytes<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = usize::try_from(read_long(input)?).ok()?;
+    take(input, len)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if input.len() < len {
+        return None;
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    Some(taken)
+}
+
+pub struct AvroFile {
+    schema: Arc<AvroType>,
+    codec: AvroCodec,
+    writer: BufWriter<fs::File>,
+    sync: [u8; 16],
+    block: Vec<u8>,
+    records: usize,
+}
+
+impl AvroFile {
+    pub fn create(
+        path: &Path,
+        schema: Arc<AvroType>,
+        codec: AvroCodec,
+    ) -> Result<AvroFile, IOError> {
+        // Any 16 bytes will do as long as they're unlikely to turn up in the data
+        let mut hasher = Sha256::new();
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update(std::process::id().to_le_bytes());
+        if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.update(now.as_nanos().to_le_bytes());
+        }
+        let mut sync = [0u8; 16];
+        sync.copy_from_slice(&hasher.finalize()[..16]);
+
+        let mut header = MAGIC.to_vec();
+        write_long(&mut header, 2);
+        write_bytes(&mut header, b"avro.schema");
+        write_bytes(&mut header, schema.to_json().to_string().as_bytes());
+        write_bytes(&mut header, b"avro.codec");
+        write_bytes(&mut header, codec.name().as_bytes());
+        write_long(&mut header, 0);
+        header.extend_from_slice(&sync);
+
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        writer.write_all(&header)?;
+        Ok(AvroFile {
+            schema,
+            codec,
+            writer,
+            sync,
+            block: vec![],
+            records: 0,
+        })
+    }
+
+    pub fn write_document(
+        &mut self,
+        action: &str,
+        source: &str,
+    ) -> Result<Option<String>, IOError> {
+        // None once the record is written, otherwise why it doesn't fit the schema
+        let action: JsonValue = serde_json::from_str(action)?;
+        let mut source = expand_dots(serde_json::from_str(source)?);
+        if let JsonValue::Object(object) = &mut source {
+            object.insert("_index".to_string(), action["index"]["_index"].clone());
+            object.insert("_id".to_string(), action["index"]["_id"].clone());
+        }
+
+        let mut record = vec![];
+        if let Err(reason) = self.schema.encode(Some(&source), "", &mut record) {
+            return Ok(Some(reason));
+        }
+        self.block.extend_from_slice(&record);
+        self.records += 1;
+        if self.records >= BLOCK_RECORDS {
+            self.flush_block()?;
+        }
+        Ok(None)
+    }
+
+    pub fn close(mut self) -> Result<(), IOError> {
+        self.flush_block()?;
+        self.writer.flush()
+    }
+
+    fn flush_block(&mut self) -> Result<(), IOError> {
+        if self.records == 0 {
+            return Ok(());
+        }
+        let data = self.codec.compress(&self.block)?;
+        let mut header = vec![];
+        write_long(&mut header, self.records as i64);
+        write_long(&mut header, data.len() as i64);
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&data)?;
+        self.writer.write_all(&self.sync)?;
+        self.block.clear();
+        self.records = 0;
+        Ok(())
+    }
+}
+
+pub fn read_ids<F: FnMut(&str, &str)>(path: &Path, mut each: F) -> Result<u64, IOError> {
+    // Walks every block of a container file, calling back with each record's _index and _id
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not an Avro object container file"));
+    }
+
+    let mut metadata = HashMap::new();
+    loop {
+        let mut count = read_varint(&mut reader)?;
+        if count == 0 {
+            break;
+        }
+        if count < 0 {
+            read_varint(&mut reader)?;
+            count = -count;
+        }
+        for _ in 0..count {
+            let key = read_chunk(&mut reader)?;
+            metadata.insert(
+                String::from_utf8_lossy(&key).to_string(),
+                read_chunk(&mut reader)?,
+            );
+        }
+    }
+    let schema = metadata
+        .get("avro.schema")
+        .and_then(|schema| serde_json::from_slice(schema).ok())
+        .and_then(|schema: JsonValue| AvroType::from_json(&schema));
+    let fields = match schema {
+        Some(AvroType::Record { fields, .. }) => fields,
+        _ => return Err(invalid("the schema isn't a record this tool wrote")),
+    };
+    let codec = metadata
+        .get("avro.codec")
+        .map(|codec| String::from_utf8_lossy(codec).to_string())
+        .unwrap_or_else(|| "null".to_string());
+    let mut sync = [0u8; 16];
+    reader.read_exact(&mut sync)?;
+
+    let mut records = 0;
+    while !reader.fill_buf()?.is_empty() {
+        let count = read_varint(&mut reader)?;
+        let data = read_chunk(&mut reader)?;
+        let mut marker = [0u8; 16];
+        reader.read_exact(&mut marker)?;
+        if marker != sync {
+            return Err(invalid("a block doesn't end with the file's sync marker"));
+        }
+
+        let data = decompress(&codec, data)?;
+        let mut input = &data[..];
+        for _ in 0..count {
+            let (mut index, mut id) = ("", "");
+            for field in &fields {
+                let read = match (field.source.as_str(), &field.data_type) {
+                    ("_index", AvroType::String) => read_bytes(&mut input).map(|v| index = utf8(v)),
+                    ("_id", AvroType::String) => read_bytes(&mut input).map(|v| id = utf8(v)),
+                    ("_id", AvroType::Nullable(_)) => match read_long(&mut input) {
+                        Some(0) => Some(()),
+                        Some(1) => read_bytes(&mut input).map(|v| id = utf8(v)),
+                        _ => None,
+                    },
+                    _ => field.data_type.skip(&mut input),
+                };
+                read.ok_or_else(|| invalid("a record doesn't match the schema"))?;
+            }
+            each(index, id);
+            records += 1;
+        }
+    }
+    Ok(records)
+}
+
+fn decompress(codec: &str, data: Vec<u8>) -> Result<Vec<u8>, IOError> {
+    match codec {
+        "null" => Ok(data),
+        "deflate" => {
+            let mut out = vec![];
+            DeflateDecoder::new(&data[..]).read_to_end(&mut out)?;
+            Ok(out)
+        }
+        "zstandard" => zstd::decode_all(&data[..]),
+        codec => Err(invalid(&format!("unknown codec {}", codec))),
+    }
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<i64, IOError> {
+    let mut bytes = vec![];
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        bytes.push(byte[0]);
+        if byte[0] & 0x80 == 0 || bytes.len() >= 10 {
+            break;
+        }
+    }
+    read_long(&mut &bytes[..]).ok_or_else(|| invalid("a length is out of range"))
+}
+
+fn read_chunk<R: Read>(reader: &mut R) -> Result<Vec<u8>, IOError> {
+    let len = usize::try_from(read_varint(reader)?).map_err(|_| invalid("negative length"))?;
+    let mut chunk = vec![0u8; len];
+    reader.read_exact(&mut chunk)?;
+    Ok(chunk)
+}
+
+fn utf8(bytes: &[u8]) -> &str {
+    std::str::from_utf8(bytes).unwrap_or("")
+}
+
+fn invalid(message: &str) -> IOError {
+    IOError::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_what_fits_and_reads_it_back() {
+        let mapping = json!({ "logs": { "mappings": { "properties": {
+            "@timestamp": { "type": "date" },
+            "n": { "type": "integer" },
+            "tags": { "type": "keyword" },
+            "host": { "properties": { "name": { "type": "keyword" } } },
+        }}}});
+        let mut lists = HashSet::new();
+        lists.insert("tags".to_string());
+        let schema = schema_from_mapping("logs", &mapping, &lists, false);
+        let names: Vec<JsonValue> = schema.to_json()["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["name"].clone())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["_index", "_id", "_timestamp", "host", "n", "tags"]
+        );
+
+        let path = std::env::temp_dir().join(format!("tana-avro-test-{}.avro", std::process::id()));
+        let mut file = AvroFile::create(&path, Arc::new(schema), AvroCodec::Deflate).unwrap();
+        let action = |id: &str| format!("{{\"index\":{{\"_index\":\"logs\",\"_id\":\"{}\"}}}}", id);
+        let fits = "{\"@timestamp\":\"2020-04-20T00:00:00Z\",\"n\":\"7\",\"tags\":\"a\",\"host.name\":\"web-1\"}";
+        assert_eq!(file.write_document(&action("1"), fits).unwrap(), None);
+        let too_big =
+            "{\"@timestamp\":0,\"n\":3000000000,\"tags\":[],\"host\":{\"name\":\"web-1\"}}";
+        assert_eq!(
+            file.write_document(&action("2"), too_big).unwrap().unwrap(),
+            "n is not a \"int\": 3000000000"
+        );
+        let missing = "{\"n\":1,\"tags\":[\"a\",\"b\"],\"host\":{}}";
+        assert!(file
+            .write_document(&action("3"), missing)
+            .unwrap()
+            .is_some());
+        // Read without an id, e.g. from NDJSON, it gets one when it's restored
+        let no_id = "{\"index\":{\"_index\":\"logs\"}}";
+        assert_eq!(file.write_document(no_id, fits).unwrap(), None);
+        file.close().unwrap();
+
+        let mut ids = vec![];
+        let records = read_ids(&path, |index, id| ids.push(format!("{}/{}", index, id))).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(records, 2);
+        assert_eq!(ids, vec!["logs/1", "logs/"]);
+    }
+}
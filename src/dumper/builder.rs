@@ -0,0 +1,275 @@
+use crate::dumper::error::DumpError;
+use crate::dumper::hosts::HostPool;
+use crate::dumper::paging::ScrollOptions;
+use crate::dumper::sink::{S3Sink, Sink};
+use crate::dumper::{Credentials, Dumper};
+use reqwest::{Certificate, ClientBuilder};
+use s3::bucket::Bucket as S3Bucket;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Auth {
+    None,
+    Basic { username: String, password: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tls {
+    // The system's trusted roots
+    Verify,
+    // Also trust this PEM certificate, e.g. a cluster's own CA
+    CaCertificate(PathBuf),
+    // Accept any certificate, for test clusters only
+    AcceptInvalidCerts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    // A level from 1 to 22
+    Zstd(i32),
+}
+
+pub struct DumperBuilder {
+    hosts: Vec<String>,
+    auth: Auth,
+    tls: Tls,
+    timeout: Duration,
+    connect_timeout: Option<Duration>,
+    concurrency: i64,
+    scroll_options: ScrollOptions,
+    output_dir: PathBuf,
+    bucket: Option<S3Bucket>,
+    sink: Option<Box<dyn Sink>>,
+    compression: Compression,
+}
+
+impl Default for DumperBuilder {
+    fn default() -> DumperBuilder {
+        DumperBuilder {
+            hosts: vec![],
+            auth: Auth::None,
+            tls: Tls::Verify,
+            timeout: Duration::from_secs(10),
+            connect_timeout: None,
+            concurrency: 3,
+            scroll_options: ScrollOptions::default(),
+            output_dir: PathBuf::from("."),
+            bucket: None,
+            sink: None,
+            compression: Compression::None,
+        }
+    }
+}
+
+impl DumperBuilder {
+    pub fn hosts<S: AsRef<str>>(mut self, hosts: &[S]) -> DumperBuilder {
+        // Any node will do, requests go round-robin across them.
+        // None is fine for dump_source, anything that talks to a cluster then fails.
+        self.hosts = hosts.iter().map(|host| host.as_ref().to_string()).collect();
+        self
+    }
+
+    pub fn auth(mut self, auth: Auth) -> DumperBuilder {
+        self.auth = auth;
+        self
+    }
+
+    pub fn tls(mut self, tls: Tls) -> DumperBuilder {
+        self.tls = tls;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> DumperBuilder {
+        // For each request as a whole, 10 seconds by default
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> DumperBuilder {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn concurrency(mut self, slices: i64) -> DumperBuilder {
+        // How many slices dump() scrolls side by side, 3 by default
+        self.concurrency = slices;
+        self
+    }
+
+    pub fn page_size(mut self, page_size: u64) -> DumperBuilder {
+        self.scroll_options.page_size = page_size;
+        self
+    }
+
+    pub fn scroll_options(mut self, options: ScrollOptions) -> DumperBuilder {
+        self.scroll_options = options;
+        self
+    }
+
+    pub fn output_dir<P: Into<PathBuf>>(mut self, dir: P) -> DumperBuilder {
+        self.output_dir = dir.into();
+        self
+    }
+
+    pub fn bucket(mut self, bucket: S3Bucket) -> DumperBuilder {
+        // Write to S3 rather than the output directory
+        self.bucket = Some(bucket);
+        self
+    }
+
+    pub fn sink(mut self, sink: Box<dyn Sink>) -> DumperBuilder {
+        self.sink = Some(sink);
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> DumperBuilder {
+        // Layered over whichever sink is used, Parquet and Avro parts are left as they are
+        self.compression = compression;
+        self
+    }
+
+    pub fn build(self) -> Result<Dumper, DumpError> {
+        let config = |message: &str| Err(DumpError::Config(message.to_string()));
+        let hosts: Vec<String> = self
+            .hosts
+            .iter()
+            .flat_map(|hosts| HostPool::parse(hosts))
+            .collect();
+        if let Some(host) = hosts
+            .iter()
+            .find(|host| !host.starts_with("http://") && !host.starts_with("https://"))
+        {
+            return Err(DumpError::Config(format!(
+                "'{}' is not an http:// or https:// URL",
+                host
+            )));
+        }
+        if self.timeout == Duration::from_secs(0) {
+            return config("The request timeout must be more than zero");
+        }
+        if self
+            .connect_timeout
+            .is_some_and(|connect| connect > self.timeout)
+        {
+            return config(
+                "The connect timeout is longer than the request timeout, it would never apply",
+            );
+        }
+        if self.concurrency < 1 {
+            return config("The concurrency must be at least one slice");
+        }
+        self.scroll_options.validate()?;
+        if let Compression::Zstd(level) = self.compression {
+            if !(1..=22).contains(&level) {
+                return config("The zstd level must be from 1 to 22");
+            }
+        }
+        if self.sink.is_some() && self.bucket.is_some() {
+            return config("Give either a sink or a bucket, the bucket would be a second sink");
+        }
+
+        if let Some(bucket) = &self.bucket {
+            // Fail now rather than once the whole index has been scrolled
+            check_bucket(bucket)?;
+        }
+
+        let mut client = ClientBuilder::new().timeout(self.timeout);
+        if let Some(timeout) = self.connect_timeout {
+            client = client.connect_timeout(timeout);
+        }
+        client = match &self.tls {
+            Tls::Verify => client,
+            Tls::CaCertificate(path) => {
+                if !hosts.iter().any(|host| host.starts_with("https://")) {
+                    return config("A CA certificate only applies to https:// hosts");
+                }
+                let pem = fs::read(path)?;
+                let certificate = Certificate::from_pem(&pem).map_err(|e| {
+                    DumpError::Config(format!(
+                        "{} is not a PEM certificate: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                client.add_root_certificate(certificate)
+            }
+            Tls::AcceptInvalidCerts => client.danger_accept_invalid_certs(true),
+        };
+
+        let creds = match self.auth {
+            Auth::None => Credentials::default(),
+            Auth::Basic { username, password } => Credentials { username, password },
+        };
+        let sink = match self.bucket.clone() {
+            Some(bucket) => Some(Box::new(S3Sink::new(bucket)) as Box<dyn Sink>),
+            None => self.sink,
+        };
+        let mut dumper =
+            Dumper::from_parts(client.build()?, HostPool::new(hosts), creds, self.bucket)
+                .scroll_options(self.scroll_options)
+                .output_dir(self.output_dir);
+        dumper.sink = sink;
+        dumper.compression = self.compression;
+        dumper.concurrency = self.concurrency;
+        Ok(dumper)
+    }
+}
+
+fn check_bucket(bucket: &S3Bucket) -> Result<(), DumpError> {
+    let (_, code) = bucket
+        .put_object("/tana-put-test-object", b"LZF", "text/plain")
+        .map_err(|e| DumpError::S3(e.to_string()))?;
+    if code != 200 {
+        return Err(DumpError::S3(format!(
+            "PUT OBJECT: {} - Incorrect details for Bucket, ensure access keys, bucket name and region are correct.",
+            code
+        )));
+    }
+    println!(
+        "Successfully verified PUT access to '{}' bucket.",
+        bucket.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_bad_combinations() {
+        let built = Dumper::builder()
+            .hosts(&["http://es-1:9200,http://es-2:9200"])
+            .auth(Auth::Basic {
+                username: "elastic".to_string(),
+                password: "changeme".to_string(),
+            })
+            .page_size(500)
+            .compression(Compression::Zstd(19))
+            .build();
+        assert!(built.is_ok());
+
+        let refused = |builder: DumperBuilder| matches!(builder.build(), Err(DumpError::Config(_)));
+        assert!(refused(Dumper::builder().hosts(&["es-1:9200"])));
+        assert!(refused(Dumper::builder().concurrency(0)));
+        assert!(refused(Dumper::builder().page_size(0)));
+        assert!(refused(Dumper::builder().page_size(50_000)));
+        assert!(refused(
+            Dumper::builder().compression(Compression::Zstd(30))
+        ));
+        assert!(refused(
+            Dumper::builder()
+                .timeout(Duration::from_secs(5))
+                .connect_timeout(Duration::from_secs(30))
+        ));
+        assert!(refused(
+            Dumper::builder()
+                .hosts(&["http://es-1:9200"])
+                .tls(Tls::CaCertificate(PathBuf::from("ca.pem")))
+        ));
+    }
+}
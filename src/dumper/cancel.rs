@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+// Shared by every clone, cancelling one cancels them all
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    // Wakes whatever is waiting in cancelled(), e.g. a request for a slow page
+    notify: Arc<watch::Sender<bool>>,
+    watch: watch::Receiver<bool>,
+}
+
+impl Default for CancelToken {
+    fn default() -> CancelToken {
+        let (notify, watch) = watch::channel(false);
+        CancelToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            notify: Arc::new(notify),
+            watch,
+        }
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        // Scrollers abandon the page in flight and clear their contexts
+        self.cancelled.store(true, Ordering::SeqCst);
+        let _ = self.notify.broadcast(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub async fn cancelled(&self) {
+        // Resolves once cancel() has been called on any clone
+        let mut watch = self.watch.clone();
+        while !self.is_cancelled() {
+            // Every clone holds the sender, so this only ends with a change
+            if watch.recv().await.is_none() {
+                return futures::future::pending().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn clones_share_cancellation() {
+        let token = CancelToken::new();
+        let scroller = token.clone();
+        assert!(!scroller.is_cancelled());
+
+        let waiting = tokio::spawn(async move { scroller.cancelled().await });
+        token.cancel();
+        waiting.await.unwrap();
+        assert!(token.clone().is_cancelled());
+    }
+}
@@ -0,0 +1,464 @@
+use crate::dumper::partition;
+use arrow::datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use arrow::json::reader::{Decoder, ReaderBuilder};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Error as IOError;
+use std::path::Path;
+use std::sync::Arc;
+
+// An offset rather than "UTC", arrow only knows named zones with chrono-tz
+const UTC: &str = "+00:00";
+// Rows are turned into Arrow a batch at a time, row groups can be much bigger
+const BATCH_SIZE: usize = 8192;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParquetCompression {
+    Snappy,
+    Zstd,
+}
+
+impl ParquetCompression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParquetCompression::Snappy => "snappy",
+            ParquetCompression::Zstd => "zstd",
+        }
+    }
+}
+
+pub fn schema_from_mapping(mapping: &JsonValue, lists: &HashSet<String>) -> Schema {
+    let properties = mapped_properties(mapping);
+
+    // The document's identity, so a restore can put everything back where it was
+    let mut fields = vec![
+        Field::new("_index", DataType::Utf8, true),
+        Field::new("_id", DataType::Utf8, true),
+    ];
+    fields.extend(struct_fields(&properties, "", lists));
+    Schema::new(fields)
+}
+
+pub fn mapped_properties(mapping: &JsonValue) -> Map<String, JsonValue> {
+    // Every index the pattern matched, first mapping of a field wins
+    let mut properties = Map::new();
+    if let Some(indices) = mapping.as_object() {
+        for index in indices.values() {
+            let mappings = &index["mappings"];
+            // Before 7.0 the properties sit under a type name
+            let found = match mappings.get("properties") {
+                Some(found) => Some(found),
+                None => mappings
+                    .as_object()
+                    .and_then(|types| types.values().find_map(|t| t.get("properties"))),
+            };
+            if let Some(JsonValue::Object(found)) = found {
+                for (name, field) in found {
+                    properties
+                        .entry(name.clone())
+                        .or_insert_with(|| field.clone());
+                }
+            }
+        }
+    }
+    properties
+}
+
+fn struct_fields(
+    properties: &Map<String, JsonValue>,
+    prefix: &str,
+    lists: &HashSet<String>,
+) -> Vec<Field> {
+    properties
+        .iter()
+        .filter_map(|(name, mapping)| {
+            let path = format!("{}{}", prefix, name);
+            let data_type = field_type(mapping, &path, lists)?;
+            // Any field can hold an array, the ones that did are lists
+            let data_type = if lists.contains(&path) && !matches!(data_type, DataType::List(_)) {
+                DataType::List(Arc::new(Field::new("item", data_type, true)))
+            } else {
+                data_type
+            };
+            Some(Field::new(name, data_type, true))
+        })
+        .collect()
+}
+
+fn field_type(mapping: &JsonValue, path: &str, lists: &HashSet<String>) -> Option<DataType> {
+    if let Some(JsonValue::Object(properties)) = mapping.get("properties") {
+        let fields = struct_fields(properties, &format!("{}.", path), lists);
+        if fields.is_empty() {
+            return None;
+        }
+        let data_type = DataType::Struct(Fields::from(fields));
+        return Some(match mapping["type"].as_str() {
+            Some("nested") => DataType::List(Arc::new(Field::new("item", data_type, true))),
+            _ => data_type,
+        });
+    }
+
+    Some(match mapping["type"].as_str().unwrap_or("object") {
+        "long" => DataType::Int64,
+        "integer" => DataType::Int32,
+        "short" => DataType::Int16,
+        "byte" => DataType::Int8,
+        "unsigned_long" => DataType::UInt64,
+        "double" | "scaled_float" => DataType::Float64,
+        "float" | "half_float" => DataType::Float32,
+        "boolean" => DataType::Boolean,
+        "date" => DataType::Timestamp(TimeUnit::Millisecond, Some(UTC.into())),
+        "date_nanos" => DataType::Timestamp(TimeUnit::Nanosecond, Some(UTC.into())),
+        // Aliases have no value in _source
+        "alias" => return None,
+        // keyword, text, ip and anything without a natural column type, as JSON text if need be
+        _ => DataType::Utf8,
+    })
+}
+
+pub fn expand_dots(value: JsonValue) -> JsonValue {
+    // {"host.name": "a"} is the same document as {"host": {"name": "a"}} to Elasticsearch
+    match value {
+        JsonValue::Object(object) => {
+            let mut expanded = Map::new();
+            for (key, value) in object {
+                let value = expand_dots(value);
+                let mut parts = key.split('.').rev();
+                let last = parts.next().unwrap_or("").to_string();
+                let mut nested = Map::new();
+                nested.insert(last, value);
+                let mut nested = JsonValue::Object(nested);
+                for part in parts {
+                    let mut outer = Map::new();
+                    outer.insert(part.to_string(), nested);
+                    nested = JsonValue::Object(outer);
+                }
+                merge(&mut expanded, nested);
+            }
+            JsonValue::Object(expanded)
+        }
+        JsonValue::Array(values) => JsonValue::Array(values.into_iter().map(expand_dots).collect()),
+        value => value,
+    }
+}
+
+fn merge(into: &mut Map<String, JsonValue>, value: JsonValue) {
+    if let JsonValue::Object(object) = value {
+        for (key, value) in object {
+            match (into.get_mut(&key), value) {
+                (Some(JsonValue::Object(existing)), JsonValue::Object(value)) => {
+                    merge(existing, JsonValue::Object(value))
+                }
+                (_, value) => {
+                    into.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+pub fn array_paths(value: &JsonValue, prefix: &str, lists: &mut HashSet<String>) {
+    // Dotted paths of every field that holds an array in this document
+    match value {
+        JsonValue::Object(object) => {
+            for (key, value) in object {
+                let path = format!("{}{}", prefix, key);
+                if let JsonValue::Array(values) = value {
+                    lists.insert(path.clone());
+                    for value in values {
+                        array_paths(value, &format!("{}.", path), lists);
+                    }
+                } else {
+                    array_paths(value, &format!("{}.", path), lists);
+                }
+            }
+        }
+        JsonValue::Array(values) => {
+            for value in values {
+                array_paths(value, prefix, lists);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn infer_properties(value: &JsonValue, properties: &mut Map<String, JsonValue>) {
+    // A mapping's properties from the documents themselves, for dumps read without a cluster.
+    // Numbers widen to double and anything else that disagrees is a keyword.
+    if let JsonValue::Object(object) = value {
+        for (key, value) in object {
+            let values = match value {
+                JsonValue::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                let inferred = match value {
+                    JsonValue::Object(_) => {
+                        let entry = properties
+                            .entry(key.clone())
+                            .or_insert_with(|| serde_json::json!({ "properties": {} }));
+                        if let Some(JsonValue::Object(nested)) = entry.get_mut("properties") {
+                            infer_properties(value, nested);
+                        }
+                        continue;
+                    }
+                    JsonValue::Bool(_) => "boolean",
+                    JsonValue::Number(n) if n.is_f64() => "double",
+                    JsonValue::Number(_) => "long",
+                    JsonValue::String(_) => "keyword",
+                    JsonValue::Null | JsonValue::Array(_) => continue,
+                };
+                let known = properties.get(key).map(|field| field["type"].as_str());
+                let data_type = match known {
+                    None => inferred,
+                    Some(Some(known)) if known == inferred => continue,
+                    Some(Some("long")) | Some(Some("double"))
+                        if matches!(inferred, "long" | "double") =>
+                    {
+                        "double"
+                    }
+                    _ => "keyword",
+                };
+                properties.insert(key.clone(), serde_json::json!({ "type": data_type }));
+            }
+        }
+    }
+}
+
+pub fn normalize(value: &JsonValue, data_type: &DataType, path: &str) -> Result<JsonValue, String> {
+    // Bend the document to the schema, Err says why a value can't fit so the row is dead-lettered
+    let doesnt_fit = || format!("{} is not a {}: {}", path, data_type, value);
+    let field_path = |name: &str| match path {
+        "" => name.to_string(),
+        path => format!("{}.{}", path, name),
+    };
+    Ok(match (data_type, value) {
+        (_, JsonValue::Null) => JsonValue::Null,
+        (DataType::List(item), JsonValue::Array(values)) => JsonValue::Array(
+            values
+                .iter()
+                .map(|value| normalize(value, item.data_type(), path))
+                .collect::<Result<_, _>>()?,
+        ),
+        (DataType::List(item), value) => {
+            JsonValue::Array(vec![normalize(value, item.data_type(), path)?])
+        }
+        (_, JsonValue::Array(values)) if values.len() == 1 => {
+            normalize(&values[0], data_type, path)?
+        }
+        (DataType::Struct(fields), JsonValue::Object(object)) => {
+            let mut normalized = Map::new();
+            for field in fields {
+                if let Some(value) = object.get(field.name()) {
+                    let value = normalize(value, field.data_type(), &field_path(field.name()))?;
+                    normalized.insert(field.name().clone(), value);
+                }
+            }
+            JsonValue::Object(normalized)
+        }
+        (DataType::Utf8, JsonValue::String(_)) => value.clone(),
+        (DataType::Utf8, value) => JsonValue::String(value.to_string()),
+        (DataType::Boolean, JsonValue::Bool(_)) => value.clone(),
+        (DataType::Boolean, JsonValue::String(text)) if text == "true" || text == "false" => {
+            JsonValue::Bool(text == "true")
+        }
+        (DataType::Timestamp(unit, _), value) => match partition::timestamp(value) {
+            Some(at) if *unit == TimeUnit::Nanosecond => at
+                .timestamp_nanos_opt()
+                .map(JsonValue::from)
+                .ok_or_else(doesnt_fit)?,
+            Some(at) => JsonValue::from(at.timestamp_millis()),
+            None => return Err(doesnt_fit()),
+        },
+        (DataType::Float32, value) | (DataType::Float64, value) => number(value)
+            .and_then(|n| n.as_f64())
+            .map(JsonValue::from)
+            .ok_or_else(doesnt_fit)?,
+        (DataType::UInt64, value) => number(value)
+            .and_then(|n| n.as_u64())
+            .map(JsonValue::from)
+            .ok_or_else(doesnt_fit)?,
+        (DataType::Int64, value)
+        | (DataType::Int32, value)
+        | (DataType::Int16, value)
+        | (DataType::Int8, value) => {
+            let (min, max) = match data_type {
+                DataType::Int32 => (i32::MIN as i64, i32::MAX as i64),
+                DataType::Int16 => (i16::MIN as i64, i16::MAX as i64),
+                DataType::Int8 => (i8::MIN as i64, i8::MAX as i64),
+                _ => (i64::MIN, i64::MAX),
+            };
+            number(value)
+                .and_then(|n| n.as_i64().or_else(|| n.as_f64().map(|f| f.trunc() as i64)))
+                .filter(|n| (min..=max).contains(n))
+                .map(JsonValue::from)
+                .ok_or_else(doesnt_fit)?
+        }
+        _ => return Err(doesnt_fit()),
+    })
+}
+
+pub fn number(value: &JsonValue) -> Option<serde_json::Number> {
+    // Elasticsearch coerces numeric strings, so do we
+    match value {
+        JsonValue::Number(n) => Some(n.clone()),
+        JsonValue::String(text) => serde_json::from_str(text.trim()).ok(),
+        _ => None,
+    }
+}
+
+pub struct ParquetFile {
+    schema: SchemaRef,
+    writer: ArrowWriter<fs::File>,
+    decoder: Decoder,
+    rows: Vec<JsonValue>,
+}
+
+impl ParquetFile {
+    pub fn create(
+        path: &Path,
+        schema: SchemaRef,
+        compression: ParquetCompression,
+        row_group_size: usize,
+    ) -> Result<ParquetFile, IOError> {
+        let compression = match compression {
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        };
+        let properties = WriterProperties::builder()
+            .set_compression(compression)
+            .set_max_row_group_size(row_group_size.max(1))
+            .build();
+        let file = fs::File::create(path)?;
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(properties)).map_err(other)?;
+        let decoder = ReaderBuilder::new(schema.clone())
+            .with_batch_size(BATCH_SIZE)
+            .build_decoder()
+            .map_err(other)?;
+
+        Ok(ParquetFile {
+            schema,
+            writer,
+            decoder,
+            rows: Vec::with_capacity(BATCH_SIZE),
+        })
+    }
+
+    pub fn write_document(
+        &mut self,
+        action: &str,
+        source: &str,
+    ) -> Result<Option<String>, IOError> {
+        // None once the row is queued, otherwise why it doesn't fit the schema
+        let action: JsonValue = serde_json::from_str(action)?;
+        let source = expand_dots(serde_json::from_str(source)?);
+        let row = normalize(&source, &DataType::Struct(self.schema.fields().clone()), "");
+        let mut row = match row {
+            Ok(JsonValue::Object(row)) => row,
+            Ok(_) => Map::new(),
+            Err(reason) => return Ok(Some(reason)),
+        };
+        row.insert("_index".to_string(), action["index"]["_index"].clone());
+        row.insert("_id".to_string(), action["index"]["_id"].clone());
+
+        self.rows.push(JsonValue::Object(row));
+        if self.rows.len() >= BATCH_SIZE {
+            self.flush_rows()?;
+        }
+        Ok(None)
+    }
+
+    pub fn close(mut self) -> Result<(), IOError> {
+        // The footer holds the schema and row groups, a file without one is unreadable
+        self.flush_rows()?;
+        self.writer.close().map_err(other)?;
+        Ok(())
+    }
+
+    fn flush_rows(&mut self) -> Result<(), IOError> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        self.decoder.serialize(&self.rows).map_err(other)?;
+        self.rows.clear();
+        if let Some(batch) = self.decoder.flush().map_err(other)? {
+            self.writer.write(&batch).map_err(other)?;
+        }
+        Ok(())
+    }
+}
+
+fn other<E: std::error::Error + Send + Sync + 'static>(e: E) -> IOError {
+    IOError::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn schema_follows_mapping_and_arrays() {
+        let mapping = json!({ "logs": { "mappings": { "properties": {
+            "@timestamp": { "type": "date" },
+            "n": { "type": "long" },
+            "tags": { "type": "keyword" },
+            "host": { "properties": { "name": { "type": "keyword" } } },
+            "spans": { "type": "nested", "properties": { "took": { "type": "integer" } } },
+        }}}});
+        let mut lists = HashSet::new();
+        let source = expand_dots(json!({ "tags": ["a", "b"], "host.name": "web-1" }));
+        array_paths(&source, "", &mut lists);
+        let schema = schema_from_mapping(&mapping, &lists);
+
+        let data_type = |name: &str| schema.field_with_name(name).unwrap().data_type().clone();
+        assert_eq!(data_type("_id"), DataType::Utf8);
+        assert_eq!(data_type("n"), DataType::Int64);
+        assert!(matches!(
+            data_type("@timestamp"),
+            DataType::Timestamp(TimeUnit::Millisecond, _)
+        ));
+        assert!(matches!(data_type("tags"), DataType::List(_)));
+        assert!(matches!(data_type("host"), DataType::Struct(_)));
+        assert!(matches!(data_type("spans"), DataType::List(_)));
+
+        let row = normalize(
+            &expand_dots(
+                json!({ "@timestamp": "2020-04-20T00:00:00Z", "n": "5", "tags": "a", "host.name": "web-1" }),
+            ),
+            &DataType::Struct(schema.fields().clone()),
+            "",
+        );
+        assert_eq!(
+            row,
+            Ok(
+                json!({ "@timestamp": 1587340800000u64, "n": 5, "tags": ["a"], "host": { "name": "web-1" } })
+            )
+        );
+        // Nothing is nulled to make it fit, the row goes to the dead letters with the reason
+        let row = normalize(
+            &json!({ "spans": [{ "took": 1 }, { "took": "slow" }] }),
+            &DataType::Struct(schema.fields().clone()),
+            "",
+        );
+        assert_eq!(row, Err("spans.took is not a Int32: \"slow\"".to_string()));
+
+        let mut properties = Map::new();
+        for source in [
+            json!({ "n": 1, "host": { "name": "web-1" } }),
+            json!({ "n": 1.5, "ok": [true] }),
+        ] {
+            infer_properties(&source, &mut properties);
+        }
+        assert_eq!(
+            JsonValue::Object(properties),
+            json!({ "n": { "type": "double" }, "host": { "properties": { "name": { "type": "keyword" } } }, "ok": { "type": "boolean" } })
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::dumper::partition;
+use csv::{QuoteStyle, WriterBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::io::{Error as IOError, ErrorKind};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    // Columns in order, dotted paths into _source, or _index and _id
+    pub fields: Vec<String>,
+    pub delimiter: char,
+    pub header: bool,
+    pub quoting: Quoting,
+    // What a missing or null field is written as
+    pub null: String,
+    pub arrays: ArrayStyle,
+    pub objects: ObjectStyle,
+}
+
+impl CsvOptions {
+    pub fn new(fields: Vec<String>, delimiter: char) -> CsvOptions {
+        CsvOptions {
+            fields,
+            delimiter,
+            header: true,
+            quoting: Quoting::Necessary,
+            null: String::new(),
+            arrays: ArrayStyle::Join(";".to_string()),
+            objects: ObjectStyle::Json,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        if self.delimiter == '\t' {
+            "tsv"
+        } else {
+            "csv"
+        }
+    }
+
+    pub fn header_row(&self) -> Result<String, IOError> {
+        self.row(&self.fields)
+    }
+
+    pub fn render(&self, action: &JsonValue, source: &JsonValue) -> Result<String, IOError> {
+        let cells: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| {
+                let value = match field.as_str() {
+                    "_index" | "_id" => action["index"].get(field),
+                    _ => partition::field_value(source, field),
+                };
+                self.cell(value)
+            })
+            .collect();
+        self.row(&cells)
+    }
+
+    fn row(&self, cells: &[String]) -> Result<String, IOError> {
+        let delimiter = self.delimiter as u32;
+        if delimiter > 0x7f {
+            return Err(IOError::new(
+                ErrorKind::InvalidInput,
+                "The delimiter must be a single ASCII character",
+            ));
+        }
+        let mut writer = WriterBuilder::new()
+            .delimiter(delimiter as u8)
+            .quote_style(self.quoting.style())
+            .from_writer(vec![]);
+        writer.write_record(cells)?;
+        let row = writer.into_inner().map_err(|e| e.into_error())?;
+        String::from_utf8(row).map_err(IOError::other)
+    }
+
+    fn cell(&self, value: Option<&JsonValue>) -> String {
+        match value {
+            None | Some(JsonValue::Null) => self.null.clone(),
+            Some(JsonValue::String(text)) => text.clone(),
+            Some(JsonValue::Array(values)) => match &self.arrays {
+                ArrayStyle::Json => JsonValue::Array(values.clone()).to_string(),
+                ArrayStyle::First => self.cell(values.first()),
+                ArrayStyle::Join(separator) => values
+                    .iter()
+                    .map(|value| self.cell(Some(value)))
+                    .collect::<Vec<String>>()
+                    .join(separator),
+            },
+            Some(object @ JsonValue::Object(_)) => match &self.objects {
+                ObjectStyle::Json => object.to_string(),
+                ObjectStyle::Pairs(separator) => {
+                    let mut pairs = vec![];
+                    self.pairs(object, "", &mut pairs);
+                    pairs.join(separator)
+                }
+            },
+            Some(value) => value.to_string(),
+        }
+    }
+
+    fn pairs(&self, value: &JsonValue, prefix: &str, pairs: &mut Vec<String>) {
+        // Leaves as dotted.key=value, arrays stay whole
+        match value {
+            JsonValue::Object(object) => {
+                for (key, value) in object {
+                    self.pairs(value, &format!("{}{}.", prefix, key), pairs);
+                }
+            }
+            value => pairs.push(format!(
+                "{}={}",
+                prefix.trim_end_matches('.'),
+                self.cell(Some(value))
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Quoting {
+    // Only cells holding the delimiter, quotes or newlines
+    Necessary,
+    Always,
+    NonNumeric,
+    // Never, even if the row can't be read back
+    Never,
+}
+
+impl Quoting {
+    fn style(&self) -> QuoteStyle {
+        match self {
+            Quoting::Necessary => QuoteStyle::Necessary,
+            Quoting::Always => QuoteStyle::Always,
+            Quoting::NonNumeric => QuoteStyle::NonNumeric,
+            Quoting::Never => QuoteStyle::Never,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrayStyle {
+    // As JSON text, e.g. ["a","b"]
+    Json,
+    // Elements joined by a separator, e.g. a;b
+    Join(String),
+    // Just the first element
+    First,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectStyle {
+    // As JSON text, e.g. {"name":"web-1"}
+    Json,
+    // Leaves as key=value joined by a separator, e.g. name=web-1;os.family=linux
+    Pairs(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn flattens_selected_fields() {
+        let action = json!({ "index": { "_index": "logs", "_id": "7" } });
+        let source = json!({
+            "user": "alice, bob",
+            "tags": ["a", "b"],
+            "host": { "name": "web-1", "os": { "family": "linux" } },
+            "n": 7,
+        });
+        let fields = ["_id", "user", "tags", "host", "host.name", "n", "missing"];
+        let mut options = CsvOptions::new(fields.iter().map(|f| f.to_string()).collect(), ',');
+        options.null = "NULL".to_string();
+
+        assert_eq!(
+            options.header_row().unwrap(),
+            "_id,user,tags,host,host.name,n,missing\n"
+        );
+        assert_eq!(
+            options.render(&action, &source).unwrap(),
+            "7,\"alice, bob\",a;b,\"{\"\"name\"\":\"\"web-1\"\",\"\"os\"\":{\"\"family\"\":\"\"linux\"\"}}\",web-1,7,NULL\n"
+        );
+
+        options.delimiter = '\t';
+        options.arrays = ArrayStyle::First;
+        options.objects = ObjectStyle::Pairs("|".to_string());
+        assert_eq!(
+            options.render(&action, &source).unwrap(),
+            "7\talice, bob\ta\tname=web-1|os.family=linux\tweb-1\t7\tNULL\n"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use crate::dumper::report::DumpReport;
+use reqwest::Error as HttpError;
+use serde_json::Error as JsonError;
+use std::fmt;
+use std::io::Error as IOError;
+
+#[derive(Debug)]
+pub enum DumpError {
+    Http(HttpError),
+    Io(IOError),
+    Json(JsonError),
+    S3(String),
+    // One or more slices failed and partial results were not allowed
+    Incomplete(Box<DumpReport>),
+    // The documents written don't match the cluster's _count, or ids repeat
+    Mismatch(Box<DumpReport>),
+    // A manifest is unreadable or its artifacts don't match it
+    Manifest(String),
+    // No node to send a request to, e.g. a shard has no started copy
+    Routing(String),
+    // Stopped by a CancelToken, the partial output was removed
+    Cancelled(Box<DumpReport>),
+    // A transform, redaction rule or script that can't be applied, e.g. a bad date format
+    Transform(String),
+    // Options that don't go together, or a sink used out of order
+    Config(String),
+    // A dump or NDJSON input that can't be read back into documents
+    Source(String),
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DumpError::Http(e) => write!(f, "HTTP error: {}", e),
+            DumpError::Io(e) => write!(f, "IO error: {}", e),
+            DumpError::Json(e) => write!(f, "Unable to parse JSON: {}", e),
+            DumpError::S3(e) => write!(f, "S3 error: {}", e),
+            DumpError::Manifest(e) => write!(f, "Manifest verification failed: {}", e),
+            DumpError::Routing(e) => write!(f, "Unable to route requests: {}", e),
+            DumpError::Transform(e) => write!(f, "Invalid transform: {}", e),
+            DumpError::Config(e) => write!(f, "Invalid configuration: {}", e),
+            DumpError::Source(e) => write!(f, "Unable to read source: {}", e),
+            DumpError::Incomplete(report) => write!(
+                f,
+                "{} of {} slices failed for index '{}'",
+                report.failed().count(),
+                report.slices.len(),
+                report.index
+            ),
+            DumpError::Cancelled(report) => {
+                write!(f, "Dump of index '{}' was cancelled", report.index)
+            }
+            DumpError::Mismatch(report) => match &report.reconciliation {
+                Some(reconciliation) => write!(
+                    f,
+                    "Dump of index '{}' does not reconcile: {}",
+                    report.index, reconciliation
+                ),
+                None => write!(f, "Dump of index '{}' does not reconcile", report.index),
+            },
+        }
+    }
+}
+
+impl std::error::Error for DumpError {}
+
+impl From<HttpError> for DumpError {
+    fn from(e: HttpError) -> DumpError {
+        DumpError::Http(e)
+    }
+}
+
+impl From<IOError> for DumpError {
+    fn from(e: IOError) -> DumpError {
+        DumpError::Io(e)
+    }
+}
+
+impl From<JsonError> for DumpError {
+    fn from(e: JsonError) -> DumpError {
+        DumpError::Json(e)
+    }
+}
@@ -0,0 +1,107 @@
+use crate::dumper::avro::AvroCodec;
+use crate::dumper::columnar::ParquetCompression;
+use crate::dumper::delimited::CsvOptions;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::io::Error as IOError;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum OutputFormat {
+    // Action line then source line, ready to POST to _bulk
+    #[default]
+    Bulk,
+    // One _source per line, with _id and _index added under these keys when given
+    #[serde(rename = "jsonl")]
+    JsonLines {
+        id_key: Option<String>,
+        index_key: Option<String>,
+    },
+    // Columnar, typed from the index mapping, with _index and _id as the first columns
+    Parquet {
+        compression: ParquetCompression,
+        row_group_size: usize,
+    },
+    // Chosen fields as CSV or TSV rows, flattened to text
+    Csv(CsvOptions),
+    // An Avro object container typed from the index mapping, documents that don't fit go to a dead letter file
+    Avro {
+        codec: AvroCodec,
+        // Every field a union with null, so missing fields fit too
+        nullable: bool,
+    },
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Bulk => "json",
+            OutputFormat::JsonLines { .. } => "jsonl",
+            OutputFormat::Parquet { .. } => "parquet",
+            OutputFormat::Csv(options) => options.extension(),
+            OutputFormat::Avro { .. } => "avro",
+        }
+    }
+
+    pub fn header(&self) -> Result<Option<String>, IOError> {
+        // The first line of every part, so each one can be opened on its own
+        match self {
+            OutputFormat::Csv(options) if options.header => options.header_row().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn render(&self, action: &str, source: &str) -> Result<String, IOError> {
+        // Both lines still carry their newlines. Parquet and Avro are encoded by their own files, not rendered
+        let (id_key, index_key) = match self {
+            OutputFormat::JsonLines { id_key, index_key } => (id_key, index_key),
+            OutputFormat::Csv(options) => {
+                let action: JsonValue = serde_json::from_str(action)?;
+                let source: JsonValue = serde_json::from_str(source)?;
+                return options.render(&action, &source);
+            }
+            _ => return Ok(format!("{}{}", action, source)),
+        };
+        if id_key.is_none() && index_key.is_none() {
+            return Ok(source.to_string());
+        }
+
+        let action: JsonValue = serde_json::from_str(action)?;
+        let mut source: JsonValue = serde_json::from_str(source)?;
+        for (key, meta) in [(id_key, "_id"), (index_key, "_index")].iter() {
+            if let (Some(key), Some(object)) = (key, source.as_object_mut()) {
+                object.insert(key.to_string(), action["index"][meta].clone());
+            }
+        }
+        Ok(format!("{}\n", source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_bulk_or_json_lines() {
+        let action = "{\"index\":{\"_index\":\"logs\",\"_id\":\"7\"}}\n";
+        let source = "{\"n\":7}\n";
+
+        assert_eq!(
+            OutputFormat::Bulk.render(action, source).unwrap(),
+            format!("{}{}", action, source)
+        );
+        let plain = OutputFormat::JsonLines {
+            id_key: None,
+            index_key: None,
+        };
+        assert_eq!(plain.render(action, source).unwrap(), source);
+        let keyed = OutputFormat::JsonLines {
+            id_key: Some("doc_id".to_string()),
+            index_key: Some("@index".to_string()),
+        };
+        assert_eq!(
+            keyed.render(action, source).unwrap(),
+            "{\"@index\":\"logs\",\"doc_id\":\"7\",\"n\":7}\n"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use serde_json::{json, Value as JsonValue};
+
+#[derive(Deserialize, Debug)]
+pub struct Hit {
+    #[serde(rename = "_index")]
+    pub index: String,
+    #[serde(rename = "_id")]
+    pub id: String,
+    // Only there when the document was indexed with custom routing
+    #[serde(rename = "_routing", default)]
+    pub routing: Option<String>,
+    // Left as Elasticsearch sent it, to be parsed into whatever the caller wants. None when
+    // _source was turned off for the search or in the mapping, or filtered down to nothing.
+    #[serde(rename = "_source", default)]
+    pub source: Option<Box<RawValue>>,
+    #[serde(default)]
+    pub sort: Option<JsonValue>,
+}
+
+impl Hit {
+    pub fn to_json(&self) -> Result<JsonValue, serde_json::Error> {
+        // As it would appear in a search response
+        let source = match &self.source {
+            Some(source) => Some(serde_json::from_str::<JsonValue>(source.get())?),
+            None => None,
+        };
+        Ok(json!({
+            "_index": self.index,
+            "_id": self.id,
+            "_routing": self.routing,
+            "_source": source,
+            "sort": self.sort,
+        }))
+    }
+}
+
+// Just enough of a search response to page through it
+#[derive(Deserialize)]
+pub(crate) struct HitPage {
+    #[serde(rename = "_scroll_id", default)]
+    pub scroll_id: String,
+    pub hits: Hits,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Hits {
+    pub hits: Vec<Hit>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_source_raw() {
+        let page: HitPage = serde_json::from_str(
+            r#"{"_scroll_id":"abc","hits":{"total":{"value":2},"hits":[
+                {"_index":"logs","_id":"1","_score":null,"_source":{"n": 1.50,"user":"alice"},"sort":[0]},
+                {"_index":"logs","_id":"2","_routing":"eu","_source":{}},
+                {"_index":"logs","_id":"3"}
+            ]}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(page.scroll_id, "abc");
+        let hits = page.hits.hits;
+        assert_eq!(
+            hits[0].source.as_ref().unwrap().get(),
+            r#"{"n": 1.50,"user":"alice"}"#
+        );
+        assert_eq!(hits[0].sort, Some(serde_json::json!([0])));
+        assert_eq!(hits[0].routing, None);
+        assert_eq!(hits[1].routing.as_deref(), Some("eu"));
+        assert!(hits[2].source.is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::dumper::error::DumpError;
+use crate::dumper::Credentials;
+use reqwest::{Client, Method, StatusCode};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How long a failed node sits out before we try it again
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+struct Host {
+    url: String,
+    failed_at: Option<Instant>,
+}
+
+impl Host {
+    fn is_healthy(&self) -> bool {
+        self.failed_at.is_none_or(|at| at.elapsed() > COOLDOWN)
+    }
+}
+
+pub struct HostPool {
+    hosts: Mutex<Vec<Host>>,
+    next: AtomicUsize,
+}
+
+impl HostPool {
+    pub fn new(urls: Vec<String>) -> HostPool {
+        HostPool {
+            hosts: Mutex::new(
+                urls.into_iter()
+                    .map(|url| Host {
+                        url,
+                        failed_at: None,
+                    })
+                    .collect(),
+            ),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn parse(list: &str) -> Vec<String> {
+        // ES_HOST style: "https://a:9200,https://b:9200"
+        list.split(',')
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .collect()
+    }
+
+    pub fn urls(&self) -> Vec<String> {
+        let hosts = self.hosts.lock().expect("Cannot obtain lock for hosts.");
+        hosts.iter().map(|host| host.url.clone()).collect()
+    }
+
+    pub fn add(&self, urls: Vec<String>) {
+        // Sniffed nodes join the rotation, known ones keep their health
+        let mut hosts = self.hosts.lock().expect("Cannot obtain lock for hosts.");
+        for url in urls {
+            if !hosts.iter().any(|host| host.url == url) {
+                hosts.push(Host {
+                    url,
+                    failed_at: None,
+                });
+            }
+        }
+    }
+
+    pub fn candidates(&self, preferred: Option<&str>) -> Vec<String> {
+        // The preferred node, then healthy nodes round-robin, then the rest as a last resort
+        let hosts = self.hosts.lock().expect("Cannot obtain lock for hosts.");
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut rotated: Vec<&Host> = hosts
+            .iter()
+            .cycle()
+            .skip(start % hosts.len().max(1))
+            .take(hosts.len())
+            .collect();
+        rotated.sort_by_key(|host| !host.is_healthy());
+
+        let mut urls: Vec<String> = preferred
+            .map(|url| vec![url.to_string()])
+            .unwrap_or_default();
+        for host in rotated {
+            if !urls.contains(&host.url) {
+                urls.push(host.url.clone());
+            }
+        }
+        urls
+    }
+
+    fn mark(&self, url: &str, failed: bool) {
+        let mut hosts = self.hosts.lock().expect("Cannot obtain lock for hosts.");
+        if let Some(host) = hosts.iter_mut().find(|host| host.url == url) {
+            host.failed_at = if failed { Some(Instant::now()) } else { None };
+        }
+    }
+
+    pub async fn send(
+        &self,
+        client: &Client,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+        creds: &Credentials,
+        preferred: Option<&str>,
+    ) -> Result<String, DumpError> {
+        // The whole body, so a response cut off part way fails over like one that never came.
+        // Scroll ids and points in time are valid cluster-wide, so any node can carry on.
+        let mut last_error = None;
+        for url in self.candidates(preferred) {
+            let mut request = client.request(method.clone(), &format!("{}{}", url, path));
+            // Auth::None sends no Authorization header at all
+            if !creds.username.is_empty() {
+                request = request.basic_auth(&creds.username, Some(&creds.password));
+            }
+            if let Some(body) = &body {
+                request = request
+                    .body(body.clone())
+                    .header("Content-Type", "application/json");
+            }
+
+            match request.send().await {
+                Ok(res) if HostPool::is_node_down(res.status()) => {
+                    println!("{} answered {}, failing over.", url, res.status());
+                    self.mark(&url, true);
+                    last_error = res.error_for_status().err();
+                }
+                Ok(res) => match res.error_for_status()?.text().await {
+                    Ok(text) => {
+                        self.mark(&url, false);
+                        return Ok(text);
+                    }
+                    Err(e) => {
+                        println!("Lost the response from {}, failing over: {}", url, e);
+                        self.mark(&url, true);
+                        last_error = Some(e);
+                    }
+                },
+                // A scroll page lost to a timeout is caught when the dump reconciles
+                Err(e) if e.is_connect() || e.is_timeout() => {
+                    println!("Unable to reach {}, failing over: {}", url, e);
+                    self.mark(&url, true);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(e.into()),
+            None => Err(DumpError::Routing(
+                "No Elasticsearch hosts configured".to_string(),
+            )),
+        }
+    }
+
+    fn is_node_down(status: StatusCode) -> bool {
+        status == StatusCode::BAD_GATEWAY
+            || status == StatusCode::SERVICE_UNAVAILABLE
+            || status == StatusCode::GATEWAY_TIMEOUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_hosts_go_to_the_back() {
+        let pool = HostPool::new(HostPool::parse(
+            "http://a:9200/, http://b:9200,http://c:9200",
+        ));
+        assert_eq!(
+            pool.urls(),
+            vec!["http://a:9200", "http://b:9200", "http://c:9200"]
+        );
+
+        pool.mark("http://a:9200", true);
+        for _ in 0..3 {
+            let candidates = pool.candidates(None);
+            assert_eq!(candidates.len(), 3);
+            assert_eq!(candidates[2], "http://a:9200");
+        }
+        assert_eq!(pool.candidates(Some("http://a:9200"))[0], "http://a:9200");
+    }
+}
@@ -0,0 +1,194 @@
+use crate::dumper::error::DumpError;
+use crate::dumper::format::OutputFormat;
+use crate::dumper::projection::SourceFilter;
+use crate::dumper::redact::AppliedRedaction;
+use crate::dumper::script::ScriptInfo;
+use crate::dumper::sink::Stored;
+use crate::dumper::transform::Pipeline;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+
+// Bump whenever the layout of the dump or this manifest changes
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub tana_version: String,
+    pub cluster: ClusterInfo,
+    pub index: String,
+    pub query: Option<JsonValue>,
+    // Set when fields were left out of _source, the documents are then incomplete
+    #[serde(default)]
+    pub source_filter: Option<SourceFilter>,
+    // Applied to every document before it was written, so they differ from the index
+    #[serde(default, skip_serializing_if = "Pipeline::is_empty")]
+    pub transforms: Pipeline,
+    // Which redaction rules ran and how many fields each changed, never the HMAC key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redactions: Vec<AppliedRedaction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<ScriptInfo>,
+    pub slices: i64,
+    pub documents: u64,
+    // False when partial results were allowed and some slices failed
+    pub complete: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    // Manifests from before formats existed are all bulk
+    #[serde(default)]
+    pub format: OutputFormat,
+    pub artifacts: Vec<Artifact>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClusterInfo {
+    pub name: String,
+    pub uuid: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Artifact {
+    // Relative to the manifest, both on disk and in S3
+    pub name: String,
+    pub compression: Option<String>,
+    pub bytes: u64,
+    pub uncompressed_bytes: u64,
+    pub sha256: String,
+    // NDJSON of the documents that didn't fit an Avro schema, not part of the dump proper
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dead_letter: bool,
+}
+
+impl ClusterInfo {
+    pub fn from_json(v: &JsonValue) -> ClusterInfo {
+        // The response of GET / on any node
+        ClusterInfo {
+            name: v["cluster_name"].as_str().unwrap_or("").to_string(),
+            uuid: v["cluster_uuid"].as_str().unwrap_or("").to_string(),
+            version: v["version"]["number"].as_str().unwrap_or("").to_string(),
+        }
+    }
+}
+
+impl Artifact {
+    pub fn from_stored(stored: Stored, uncompressed_bytes: u64) -> Artifact {
+        // As a sink reported it, after any compression it added
+        Artifact {
+            name: stored.name,
+            compression: stored.compression,
+            bytes: stored.bytes,
+            uncompressed_bytes,
+            sha256: stored.sha256,
+            dead_letter: false,
+        }
+    }
+
+    pub fn verify(&self, content: &[u8]) -> Result<(), DumpError> {
+        self.check(content.len() as u64, &hex::encode(Sha256::digest(content)))
+    }
+
+    fn check(&self, bytes: u64, sha256: &str) -> Result<(), DumpError> {
+        if bytes != self.bytes {
+            return Err(DumpError::Manifest(format!(
+                "{} is {} bytes, manifest says {}",
+                self.name, bytes, self.bytes
+            )));
+        }
+        if sha256 != self.sha256 {
+            return Err(DumpError::Manifest(format!(
+                "{} has SHA-256 {}, manifest says {}",
+                self.name, sha256, self.sha256
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Manifest {
+    pub fn filename(index: &str) -> String {
+        format!("{}.manifest.json", index)
+    }
+
+    pub fn load(path: &str) -> Result<Manifest, DumpError> {
+        let content = fs::read(path)?;
+        Manifest::from_slice(&content)
+    }
+
+    pub fn from_slice(content: &[u8]) -> Result<Manifest, DumpError> {
+        let manifest: Manifest = serde_json::from_slice(content)?;
+        if manifest.format_version > FORMAT_VERSION {
+            return Err(DumpError::Manifest(format!(
+                "Format version {} is newer than this tana supports ({})",
+                manifest.format_version, FORMAT_VERSION
+            )));
+        }
+        Ok(manifest)
+    }
+
+    pub fn verify_dir(&self, dir: &Path) -> Result<(), DumpError> {
+        // Must pass before anything in the dump is trusted or loaded
+        for artifact in &self.artifacts {
+            let path = dir.join(&artifact.name);
+            let bytes = fs::metadata(&path)?.len();
+            artifact.check(bytes, &sha256_file(&path.to_string_lossy())?)?;
+        }
+        Ok(())
+    }
+}
+
+fn sha256_file(path: &str) -> Result<String, io::Error> {
+    // Stream it through, dumps are far too big to read into memory
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dumper::sink::{DiskSink, Sink};
+
+    #[tokio::test]
+    async fn verify_dir_catches_modified_artifact() {
+        // Written through a sink, as a dump writes its parts
+        let dir = std::env::temp_dir().join(format!("tana-manifest-test-{}", std::process::id()));
+        let mut sink = DiskSink::new(&dir);
+        sink.create("test.json", None).await.unwrap();
+        sink.write(b"{\"index\":{}}\n{}\n").await.unwrap();
+        let artifact = Artifact::from_stored(sink.flush().await.unwrap(), 16);
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            tana_version: env!("CARGO_PKG_VERSION").to_string(),
+            cluster: ClusterInfo::default(),
+            index: "test".to_string(),
+            query: None,
+            source_filter: None,
+            transforms: Pipeline::default(),
+            redactions: vec![],
+            script: None,
+            slices: 1,
+            documents: 1,
+            complete: true,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            format: OutputFormat::Bulk,
+            artifacts: vec![artifact],
+        };
+        assert!(manifest.verify_dir(&dir).is_ok());
+
+        // Same size, different content
+        fs::write(dir.join("test.json"), "{\"index\":{}}\n[]\n").unwrap();
+        let verified = manifest.verify_dir(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(verified.is_err());
+    }
+}
@@ -1,28 +1,104 @@
+use crate::dumper::hosts::HostPool;
+use crate::dumper::partition::PartitionWriter;
+use crate::dumper::parts::MappedSchema;
 use crate::dumper::scroller::Scroller;
-use flate2::bufread::GzEncoder;
-use flate2::Compression;
+use crate::dumper::workdir::WorkDir;
+use chrono::Utc;
 use futures::future::join_all;
-use reqwest::Error as HttpError;
-use reqwest::{Client, ClientBuilder};
+use futures::stream::Stream;
+use reqwest::{Client, Method};
 use s3::bucket::Bucket as S3Bucket;
 use s3::credentials::Credentials as S3Credentials;
-use std::io::prelude::*;
+use serde_json::Value as JsonValue;
+use serde_json::{json, Map};
+use std::collections::{HashMap, HashSet};
 use std::io::Error as IOError;
-use std::io::{BufReader, BufWriter};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use std::{env, fs, process};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use std::{env, fs};
 
+pub use avro::AvroCodec;
+pub use builder::{Auth, Compression, DumperBuilder, Tls};
+pub use cancel::CancelToken;
+pub use columnar::ParquetCompression;
+pub use delimited::{ArrayStyle, CsvOptions, ObjectStyle, Quoting};
+pub use error::DumpError;
+pub use format::OutputFormat;
+pub use hit::Hit;
+pub use manifest::{Artifact, ClusterInfo, Manifest};
+pub use paging::ScrollOptions;
+pub use partition::Partitioning;
+pub use parts::PartLimits;
+pub use plan::SlicePlan;
+pub use projection::SourceFilter;
+pub use reconcile::Reconciliation;
+pub use redact::{AppliedRedaction, Redaction, RedactionRule, Redactor, Selector};
+pub use report::{DumpReport, SliceReport, SliceStatus};
+pub use routing::ShardRoute;
+pub use script::{Outcome, Script, ScriptInfo, ScriptLimits};
+pub use sink::{DiskSink, GzipSink, S3Sink, Sink, Stored, ZstdSink};
+pub use source::{DumpSource, NdjsonSource, Source};
+pub use transform::{Condition, Pipeline, Transform};
+pub use verify::{verify_dump, verify_manifest, verify_s3, VerifyReport};
+
+mod avro;
+mod builder;
+mod cancel;
+mod columnar;
+mod delimited;
+mod error;
+mod format;
+mod hit;
+mod hosts;
+mod manifest;
+mod paging;
+mod partition;
+mod parts;
+mod plan;
+mod projection;
+mod reconcile;
+mod redact;
+mod report;
+mod routing;
+mod script;
 mod scroller;
+mod sink;
+mod source;
+mod transform;
+mod verify;
+mod workdir;
 
 pub struct Dumper {
     client: Client,
     creds: Credentials,
-    url: String,
+    hosts: Arc<HostPool>,
     bucket: Option<S3Bucket>,
+    allow_partial: bool,
+    verify_ids: bool,
+    query: Option<JsonValue>,
+    source_filter: SourceFilter,
+    transforms: Arc<Pipeline>,
+    redactor: Redactor,
+    script: Option<Arc<Script>>,
+    scroll_options: ScrollOptions,
+    index_scroll_options: HashMap<String, ScrollOptions>,
+    shard_routing: bool,
+    cancel: CancelToken,
+    output_dir: PathBuf,
+    work_dir: Option<PathBuf>,
+    part_limits: PartLimits,
+    partitioning: Partitioning,
+    format: OutputFormat,
+    sink: Option<Box<dyn Sink>>,
+    // Layered over the sink when a dump is written
+    compression: Compression,
+    // Slices for dump()
+    concurrency: i64,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Credentials {
     username: String,
     password: String,
@@ -34,27 +110,15 @@ impl Dumper {
     // with Dumper to incrementally dump indices to disk.   //
     // An almost exact clone of ElasticDump but rustic.     //
     // ======================================================
-    pub fn new(url: &str, username: &str, password: &str) -> Result<Dumper, HttpError> {
-        // Create new client with API base URL - default 10s timeout
-        let ten_seconds = Duration::new(10, 0);
-        let invalid_certs = !(env::var("TLS_NO_VERIFY").unwrap_or("".to_string()) == "");
-        let client = ClientBuilder::new()
-            .timeout(ten_seconds)
-            .danger_accept_invalid_certs(invalid_certs)
-            .build()?;
-
-        // Setup creds
-        let creds = Credentials {
-            username: username.to_string(),
-            password: password.to_string(),
-        };
-
-        Ok(Dumper {
-            client: client,
-            url: url.to_string(),
-            creds: creds,
-            bucket: None,
-        })
+    pub fn new(url: &str, username: &str, password: &str) -> Result<Dumper, DumpError> {
+        // Verified TLS and a 10s timeout, DumperBuilder has the rest of the options
+        Dumper::builder()
+            .hosts(&[url])
+            .auth(Auth::Basic {
+                username: username.to_string(),
+                password: password.to_string(),
+            })
+            .build()
     }
 
     pub fn new_with_bucket(
@@ -65,23 +129,7 @@ impl Dumper {
         access_key: &str,
         secret_key: &str,
         region: &str,
-    ) -> Result<Dumper, HttpError> {
-        // Create new client with API base URL - default 10s timeout
-        let ten_seconds = Duration::new(10, 0);
-
-        // TODO: only accept invalid certs if told to do so...
-        // i.e. read from environment variable or force CA otherwise
-        let client = ClientBuilder::new()
-            .timeout(ten_seconds)
-            .danger_accept_invalid_certs(true)
-            .build()?;
-
-        // Setup creds
-        let creds = Credentials {
-            username: username.to_string(),
-            password: password.to_string(),
-        };
-
+    ) -> Result<Dumper, DumpError> {
         // Setup bucket
         let s3creds: S3Credentials = S3Credentials::new(
             Some(access_key.to_string()),
@@ -89,304 +137,1029 @@ impl Dumper {
             None,
             None,
         );
+        let region = region
+            .parse()
+            .map_err(|e| DumpError::S3(format!("Unknown region '{}': {}", region, e)))?;
+        let bucket: S3Bucket = S3Bucket::new(bucket_name, region, s3creds)
+            .map_err(|e| DumpError::S3(e.to_string()))?;
 
-        let bucket: S3Bucket = S3Bucket::new(bucket_name, region.parse().unwrap(), s3creds)
-            .expect("Unable to create S3 bucket option");
+        Dumper::builder()
+            .hosts(&[url])
+            .auth(Auth::Basic {
+                username: username.to_string(),
+                password: password.to_string(),
+            })
+            .bucket(bucket)
+            .build()
+    }
 
-        // Test bucket configuration
-        let content = "LZF".as_bytes();
-        let (_, code) = bucket
-            .put_object("/tana-put-test-object", content, "text/plain")
-            .unwrap();
-        if code != 200 {
-            println!("PUT OBJECT: {} - Incorrect details for Bucket, ensure access keys, bucket name and region are correct.", code);
-            process::exit(1);
-        } else {
-            println!(
-                "Successfully verified PUT access to '{}' bucket.",
-                bucket_name
-            );
-        }
+    pub fn builder() -> DumperBuilder {
+        DumperBuilder::default()
+    }
 
-        Ok(Dumper {
-            client: client,
-            url: url.to_string(),
-            creds: creds,
-            bucket: Some(bucket),
-        })
+    fn from_parts(
+        client: Client,
+        hosts: HostPool,
+        creds: Credentials,
+        bucket: Option<S3Bucket>,
+    ) -> Dumper {
+        Dumper {
+            client,
+            hosts: Arc::new(hosts),
+            creds,
+            bucket,
+            allow_partial: false,
+            verify_ids: false,
+            query: None,
+            source_filter: SourceFilter::default(),
+            transforms: Arc::new(Pipeline::default()),
+            redactor: Redactor::default(),
+            script: None,
+            scroll_options: ScrollOptions::default(),
+            index_scroll_options: HashMap::new(),
+            shard_routing: false,
+            cancel: CancelToken::new(),
+            output_dir: PathBuf::from("."),
+            work_dir: None,
+            part_limits: PartLimits::default(),
+            partitioning: Partitioning::None,
+            format: OutputFormat::Bulk,
+            sink: None,
+            compression: Compression::None,
+            concurrency: 3,
+        }
     }
 
-    pub async fn get_indices(&mut self) -> Result<Vec<String>, HttpError> {
+    pub async fn get_indices(&mut self) -> Result<Vec<String>, DumpError> {
         // Use _cat/indices to get the names of all the indices
-        let url = format!("{}/_cat/indices?h=index", &self.url);
-
-        let res = self
-            .client
-            .get(&url)
-            .basic_auth(&self.creds.username, Some(&self.creds.password))
-            .send()
+        let text = self
+            .request(Method::GET, "/_cat/indices?h=index", None)
             .await?;
 
-        let text = res.text().await?;
-
         // Split and collect them - POSSIBLY RETURN ITERATOR INSTEAD?
         let indices = text
-            .split("\n")
+            .split('\n')
             .map(|index| index.to_string())
             .collect::<Vec<String>>();
 
         Ok(indices)
     }
 
-    pub async fn dump_index_to_disk(self, index: String, slices: i64) -> Result<(), HttpError> {
-        // Create a lot of work for ourselves
-        //   They each need their own copy of the url, credentials and index
-        let mut work = vec![];
-        for i in 0..slices {
-            println!("Creating scroller {}", i);
-            let scroller = Scroller::new(self.url.clone(), index.clone(), i, slices)?;
-            work.push(scroller.scroll(self.creds.clone()));
-        }
+    pub fn allow_partial(mut self, allow: bool) -> Dumper {
+        // Consolidate and upload even when some slices failed
+        self.allow_partial = allow;
+        self
+    }
 
-        // Wait for that work to finish
-        join_all(work).await;
+    pub fn verify_ids(mut self, verify: bool) -> Dumper {
+        // Check no document id was written twice, costs memory per id
+        self.verify_ids = verify;
+        self
+    }
 
-        // Consolidate files
-        Dumper::consolidate_files(&*index)
-            .await
-            .expect("Unable to consolidate files.");
+    pub fn query(mut self, query: JsonValue) -> Dumper {
+        // Only dump (and count) the documents matching this query
+        self.query = Some(query);
+        self
+    }
 
-        Ok(())
+    pub fn source_filter(mut self, filter: SourceFilter) -> Dumper {
+        // Leave fields out of every document, recorded in the manifest as a partial dump
+        self.source_filter = filter;
+        self
     }
 
-    pub async fn crunch_index_to_disk(self, index: String, slices: i64) -> Result<(), HttpError> {
-        // Create a lot of work for ourselves
-        //   They each need their own copy of the url, credentials and index
-        let mut work = vec![];
-        for i in 0..slices {
-            println!("Creating scroller {}", i);
-            let scroller = Scroller::new(self.url.clone(), index.clone(), i, slices + 1)?;
-            work.push(scroller.scroll(self.creds.clone()));
+    pub fn transforms(mut self, transforms: Pipeline) -> Dumper {
+        // Rewrite or drop documents between the scroll and the output, whatever its format
+        self.transforms = Arc::new(transforms);
+        self
+    }
+
+    pub fn redactor(mut self, redactor: Redactor) -> Dumper {
+        // Mask, remove, hash or truncate personal data before anything is written
+        self.redactor = redactor;
+        self
+    }
+
+    pub fn script(mut self, script: Script) -> Dumper {
+        // Custom logic per document, after the redactor and transforms
+        self.script = Some(Arc::new(script));
+        self
+    }
+
+    pub fn scroll_options(mut self, options: ScrollOptions) -> Dumper {
+        // Page size and keep-alive for every index without its own
+        self.scroll_options = options;
+        self
+    }
+
+    pub fn index_scroll_options(mut self, index: &str, options: ScrollOptions) -> Dumper {
+        // Overrides the run's scroll options for this one index
+        self.index_scroll_options.insert(index.to_string(), options);
+        self
+    }
+
+    pub fn shard_routing(mut self, enabled: bool) -> Dumper {
+        // Pin slices to shards and read them from the nodes that hold them
+        self.shard_routing = enabled;
+        self
+    }
+
+    pub fn output_dir<P: Into<PathBuf>>(mut self, dir: P) -> Dumper {
+        // Where dumps and their manifests are written, the current directory by default
+        self.output_dir = dir.into();
+        self
+    }
+
+    pub fn work_dir<P: Into<PathBuf>>(mut self, dir: P) -> Dumper {
+        // Where each run keeps its slice files until they're consolidated
+        self.work_dir = Some(dir.into());
+        self
+    }
+
+    pub fn part_limits(mut self, limits: PartLimits) -> Dumper {
+        // Rotate the output into numbered parts instead of one file
+        self.part_limits = limits;
+        self
+    }
+
+    pub fn partitioning(mut self, partitioning: Partitioning) -> Dumper {
+        // Split the output by day, hour or field value, each partition in its own directory
+        self.partitioning = partitioning;
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Dumper {
+        // Bulk pairs by default, or one document per line
+        self.format = format;
+        self
+    }
+
+    pub fn sink(mut self, sink: Box<dyn Sink>) -> Dumper {
+        // Where finished parts and the manifest go, the output directory by default
+        self.sink = Some(sink);
+        self
+    }
+
+    pub fn cancel_token(mut self, token: CancelToken) -> Dumper {
+        // Cancel the token to stop every scroller and clear its context on the cluster
+        self.cancel = token;
+        self
+    }
+
+    pub async fn count(&self, index: &str) -> Result<u64, DumpError> {
+        // Use _count with the same query the scrollers use
+        let mut payload = json!({});
+        if let Some(query) = &self.query {
+            payload["query"] = query.clone();
         }
 
-        // Wait for that work to finish
-        join_all(work).await;
+        let v: JsonValue = serde_json::from_str(
+            &self
+                .request(Method::GET, &format!("/{}/_count", index), Some(payload))
+                .await?,
+        )?;
 
-        // Consolidate files
-        Dumper::consolidate_files(&*index)
-            .await
-            .expect("Unable to consolidate files.");
+        Ok(v["count"].as_u64().unwrap_or(0))
+    }
+
+    pub async fn primary_shards(&self, index: &str) -> Result<u64, DumpError> {
+        // _cat/shards also copes with aliases and patterns spanning indices
+        let path = format!("/_cat/shards/{}?format=json&h=index,shard,prirep", index);
+        let shards: Vec<JsonValue> =
+            serde_json::from_str(&self.request(Method::GET, &path, None).await?)?;
+
+        Ok(shards
+            .iter()
+            .filter(|shard| shard["prirep"].as_str() == Some("p"))
+            .count() as u64)
+    }
+
+    pub async fn plan_slices(&self, index: &str, budget: u64) -> Result<SlicePlan, DumpError> {
+        // Pick a slice count from the shard layout instead of guessing
+        let shards = self.primary_shards(index).await?;
+        let plan = SlicePlan::new(index, shards, budget, plan::file_handle_limit());
+        println!("{}", plan);
+        Ok(plan)
+    }
+
+    pub async fn shard_routes(
+        &self,
+        index: &str,
+        slices: i64,
+    ) -> Result<Vec<ShardRoute>, DumpError> {
+        // Where every copy of every shard lives, and how to reach those nodes
+        let path = format!(
+            "/_cat/shards/{}?format=json&h=index,shard,prirep,state,node",
+            index
+        );
+        let shards: Vec<JsonValue> =
+            serde_json::from_str(&self.request(Method::GET, &path, None).await?)?;
+
+        let node_urls = self.node_urls().await?;
+        routing::plan_routes(&shards, &node_urls, slices).map_err(DumpError::Routing)
+    }
+
+    pub async fn sniff(&self) -> Result<Vec<String>, DumpError> {
+        // Add every node with HTTP enabled to the rotation
+        let mut urls: Vec<String> = self.node_urls().await?.into_values().collect();
+        urls.sort();
+        println!("Sniffed Elasticsearch nodes: {}", urls.join(", "));
+        self.hosts.add(urls);
+        Ok(self.hosts.urls())
+    }
 
-        // Compress the result
-        Dumper::compress_final(index)
+    async fn node_urls(&self) -> Result<HashMap<String, String>, DumpError> {
+        let nodes: JsonValue =
+            serde_json::from_str(&self.request(Method::GET, "/_nodes/http", None).await?)?;
+
+        // Talk to the other nodes the same way we talk to the seeds
+        let seed = self.hosts.urls().into_iter().next().unwrap_or_default();
+        let scheme = seed.split("://").next().unwrap_or("http").to_string();
+        Ok(routing::node_urls(&nodes, &scheme))
+    }
+
+    pub async fn cluster_info(&self) -> Result<ClusterInfo, DumpError> {
+        // The root endpoint names the cluster and its version
+        let v: JsonValue = serde_json::from_str(&self.request(Method::GET, "/", None).await?)?;
+
+        Ok(ClusterInfo::from_json(&v))
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<JsonValue>,
+    ) -> Result<String, DumpError> {
+        // Any healthy node will do, failing over to the next
+        self.hosts
+            .send(
+                &self.client,
+                method,
+                path,
+                body.map(|body| body.to_string()),
+                &self.creds,
+                None,
+            )
             .await
-            .expect("Unable to compress final file.");
+    }
 
-        Ok(())
+    pub fn stream_index(
+        &self,
+        index: &str,
+        options: ScrollOptions,
+    ) -> impl Stream<Item = Result<Hit, DumpError>> + Send {
+        // Documents as they arrive, nothing is written anywhere
+        self.stream_slice(index, 0, 1, options)
     }
 
-    pub async fn dump_index_to_s3(self, index: String, slices: i64) -> Result<(), HttpError> {
-        // Create a lot of work for ourselves
-        //   They each need their own copy of the url, credentials and index
-        let mut work = vec![];
-        for i in 0..slices {
-            println!("Creating scroller {}", i);
-            let scroller = Scroller::new(self.url.clone(), index.clone(), i, slices + 1)?;
-            work.push(scroller.scroll(self.creds.clone()));
+    pub fn stream_slices(
+        &self,
+        index: &str,
+        slices: i64,
+        options: ScrollOptions,
+    ) -> Vec<impl Stream<Item = Result<Hit, DumpError>> + Send> {
+        // One stream per slice, for the caller to read side by side
+        (0..slices)
+            .map(|slice_id| self.stream_slice(index, slice_id, slices, options.clone()))
+            .collect()
+    }
+
+    fn stream_slice(
+        &self,
+        index: &str,
+        slice_id: i64,
+        slices: i64,
+        options: ScrollOptions,
+    ) -> impl Stream<Item = Result<Hit, DumpError>> + Send {
+        // The query and source filter apply, transforms, redaction and scripts are for dumps
+        Scroller::new(
+            self.client.clone(),
+            self.hosts.clone(),
+            index.to_string(),
+            slice_id,
+            slices,
+            options,
+            self.cancel.clone(),
+        )
+        .with_query(self.query.clone())
+        .with_source_filter(&self.source_filter)
+        .hits(self.creds.clone())
+    }
+
+    pub async fn dump(self, index: String) -> Result<DumpReport, DumpError> {
+        // As many slices as the builder's concurrency
+        let slices = self.concurrency;
+        self.dump_index(index, slices).await
+    }
+
+    pub async fn dump_index(mut self, index: String, slices: i64) -> Result<DumpReport, DumpError> {
+        self.check_sink()?;
+        self.transforms.validate()?;
+        // Scroll every slice to its own temp file
+        let work = self.create_work_dir(&index)?;
+        let report = self.scroll_slices(&index, slices, &work).await?;
+        let mut report = self.check_report(report, &work).await?;
+
+        // Consolidate into parts and send each through the sink, the manifest goes last
+        // so it only exists for whole dumps
+        let mut sink = self.resolve_sink();
+        let artifacts = self
+            .write_parts(&mut report, &work, &mut sink, false)
+            .await?;
+        self.write_manifest(&report, artifacts, &mut sink, None)
+            .await?;
+
+        Ok(report)
+    }
+
+    pub async fn dump_source<S: Source>(
+        mut self,
+        index: String,
+        mut source: S,
+    ) -> Result<DumpReport, DumpError> {
+        // Documents from anywhere through the same redaction, transforms, script, parts and
+        // sink as a dump, as a single slice and without a cluster
+        self.check_sink()?;
+        self.transforms.validate()?;
+        let work = self.create_work_dir(&index)?;
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let redactor = self.redactor.counted();
+        let result = self
+            .read_source(&index, &mut source, &redactor, &work.slice_file(0))
+            .await;
+        if let Err(e) = &result {
+            println!("Reading {} failed: {}", index, e);
         }
+        let report = DumpReport {
+            index: index.clone(),
+            slices: vec![SliceReport::new(0, result, started.elapsed())],
+            started_at,
+            duration: started.elapsed(),
+            reconciliation: None,
+            redactions: redactor.applied(),
+        };
+        if self.cancel.is_cancelled() {
+            Dumper::discard_slices(&work);
+            return Err(DumpError::Cancelled(Box::new(report)));
+        }
+        let mut report = match source.expected() {
+            _ if !report.is_complete() => self.keep_partial(report, &work)?,
+            Some(expected) => self.reconcile(report, &work, expected, "the source")?,
+            None => report,
+        };
 
-        // Wait for that work to finish
-        join_all(work).await;
+        let mut sink = self.resolve_sink();
+        let artifacts = self
+            .write_parts(&mut report, &work, &mut sink, true)
+            .await?;
+        self.write_manifest(&report, artifacts, &mut sink, Some(source.cluster()))
+            .await?;
 
-        // Consolidate files
-        Dumper::consolidate_files(&*index)
-            .await
-            .expect("Unable to consolidate files.");
-
-        // Send final file to S3
-        let file: Vec<u8> =
-            fs::read(format!("{}.json", index)).expect("Unable to open target file.");
-        let metadata = fs::metadata(format!("{}.json", index))
-            .expect("Unable to read metadata of target file.");
-        if self.bucket.is_some() && metadata.len() != 0 {
-            println!("PUT {} to S3.", index);
-            let (_, code) = self
-                .bucket
-                .unwrap()
-                .put_object(&format!("{}.json", index), &file, "data/binary")
-                .unwrap();
-            if code != 200 {
-                println!("Error putting file to S3: {}", code);
-                process::exit(1);
-            } else {
-                println!("Success. Removing {} files.", index);
-                fs::remove_file(format!("{}.json", index));
+        Ok(report)
+    }
+
+    async fn read_source<S: Source>(
+        &self,
+        index: &str,
+        source: &mut S,
+        redactor: &Redactor,
+        path: &Path,
+    ) -> Result<(u64, u64, u64), DumpError> {
+        // A page at a time into a slice file, as a scroller would write it
+        let mut file = fs::File::create(path)?;
+        let (mut documents, mut bytes, mut dropped) = (0, 0, 0);
+        while !self.cancel.is_cancelled() {
+            let hits = source.next_page().await?;
+            if hits.is_empty() {
+                break;
             }
+            let mut lines = String::new();
+            for mut hit in hits {
+                // Documents read without an index belong to the one being written
+                if hit.index.is_empty() {
+                    hit.index = index.to_string();
+                }
+                let script = self.script.as_deref();
+                let hit = hit.to_json()?;
+                if scroller::write_bulk(&hit, redactor, &self.transforms, script, &mut lines)? {
+                    documents += 1;
+                } else {
+                    dropped += 1;
+                }
+            }
+            file.write_all(lines.as_bytes())?;
+            bytes += lines.len() as u64;
+            println!("Read {} documents into {}", documents, index);
+        }
+        file.flush()?;
+        Ok((documents, bytes, dropped))
+    }
+
+    pub async fn dump_index_to_disk(
+        self,
+        index: String,
+        slices: i64,
+    ) -> Result<DumpReport, DumpError> {
+        let sink = self.named_sink("disk")?;
+        self.sink(sink).dump_index(index, slices).await
+    }
+
+    pub async fn crunch_index_to_disk(
+        self,
+        index: String,
+        slices: i64,
+    ) -> Result<DumpReport, DumpError> {
+        let sink = self.named_sink("disk")?;
+        let mut dumper = self.sink(sink);
+        dumper.compression = Compression::Gzip;
+        dumper.dump_index(index, slices).await
+    }
+
+    pub async fn dump_index_to_s3(
+        self,
+        index: String,
+        slices: i64,
+    ) -> Result<DumpReport, DumpError> {
+        let sink = self.named_sink("s3")?;
+        self.sink(sink).dump_index(index, slices).await
+    }
+
+    pub async fn crunch_index_to_s3(
+        self,
+        index: String,
+        slices: i64,
+    ) -> Result<DumpReport, DumpError> {
+        let sink = self.named_sink("s3")?;
+        let mut dumper = self.sink(sink);
+        dumper.compression = Compression::Gzip;
+        dumper.dump_index(index, slices).await
+    }
+
+    fn resolve_sink(&mut self) -> Box<dyn Sink> {
+        // The sink given, or the output directory, with the compression over it
+        let sink = match self.sink.take() {
+            Some(sink) => sink,
+            None => Box::new(DiskSink::new(&self.output_dir)),
+        };
+        match self.compression {
+            Compression::None => sink,
+            Compression::Gzip => Box::new(GzipSink::new(sink)),
+            Compression::Zstd(level) => Box::new(ZstdSink::new(sink, level)),
         }
+    }
 
+    fn check_sink(&self) -> Result<(), DumpError> {
+        // Refuse before scrolling anything, rather than running out of memory on a big part
+        let holds_objects = self.sink.as_ref().is_some_and(|sink| sink.holds_objects());
+        if holds_objects && self.part_limits.max_bytes.is_none() {
+            return Err(DumpError::Config(
+                "This sink uploads each part whole, set a part size so every part fits in memory"
+                    .to_string(),
+            ));
+        }
         Ok(())
     }
 
-    pub async fn crunch_index_to_s3(self, index: String, slices: i64) -> Result<(), HttpError> {
-        // Create a lot of work for ourselves
-        //   They each need their own copy of the url, credentials and index
-        let mut work = vec![];
-        for i in 0..slices {
-            println!("Creating scroller {}", i);
-            let scroller = Scroller::new(self.url.clone(), index.clone(), i, slices + 1)?;
-            work.push(scroller.scroll(self.creds.clone()));
+    pub fn named_sink(&self, name: &str) -> Result<Box<dyn Sink>, DumpError> {
+        // The built-in destinations, by the names --dest takes
+        match (name, &self.bucket) {
+            ("disk", _) => Ok(Box::new(DiskSink::new(&self.output_dir))),
+            ("s3", Some(bucket)) => Ok(Box::new(S3Sink::new(bucket.clone()))),
+            ("s3", None) => Err(DumpError::Config(
+                "The s3 sink needs a Dumper created with a bucket".to_string(),
+            )),
+            (name, _) => Err(DumpError::Config(format!(
+                "No sink called '{}', must be [disk|s3]",
+                name
+            ))),
         }
+    }
 
-        // Wait for that work to finish
-        join_all(work).await;
+    async fn write_parts(
+        &self,
+        report: &mut DumpReport,
+        work: &WorkDir,
+        sink: &mut Box<dyn Sink>,
+        offline: bool,
+    ) -> Result<Vec<Artifact>, DumpError> {
+        // Parts are staged on local disk, Parquet needs a file it can seek in
+        let staging = self.create_work_dir(&format!("{}-parts", report.index))?;
+        let schema = self.mapped_schema(report, work, offline).await?;
+        let mut parts = PartitionWriter::new(
+            staging.path(),
+            &report.index,
+            self.partitioning.clone(),
+            self.format.clone(),
+            schema,
+            self.part_limits.clone(),
+        );
+        Dumper::consolidate_files(report, work, &mut parts, self.allow_partial).await?;
 
-        // Consolidate files
-        Dumper::consolidate_files(&*index)
-            .await
-            .expect("Unable to consolidate files.");
+        let mut artifacts = vec![];
+        for part in parts.finish()? {
+            // Partitioned parts are named by their path under the staging directory
+            let name = part
+                .path
+                .strip_prefix(staging.path())
+                .unwrap_or(&part.path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let compression = match self.format {
+                // Parquet and Avro compress their own pages
+                _ if part.dead_letter => None,
+                OutputFormat::Parquet { compression, .. } => Some(compression.name()),
+                OutputFormat::Avro { codec, .. } => Some(codec.name()),
+                _ => None,
+            };
+            let stored = Dumper::send_file(sink, &part.path, &name, compression).await?;
+            if part.dead_letter {
+                println!(
+                    "{} documents didn't fit the schema, they're in {}",
+                    part.documents, stored.name
+                );
+            }
+            let mut artifact = Artifact::from_stored(stored, part.bytes);
+            artifact.dead_letter = part.dead_letter;
+            artifacts.push(artifact);
+        }
+        staging.remove()?;
+        Ok(artifacts)
+    }
 
-        // Compress the result
-        Dumper::compress_final(index.clone())
-            .await
-            .expect("Unable to compress final file.");
-
-        // Send final file to S3
-        let file: Vec<u8> = fs::read(format!("{}.gz", index)).expect("Unable to open target file.");
-        let metadata =
-            fs::metadata(format!("{}.gz", index)).expect("Unable to read metadata of target file.");
-        if self.bucket.is_some() && metadata.len() != 0 {
-            println!("PUT {} to S3.", index);
-            let (_, code) = self
-                .bucket
-                .unwrap()
-                .put_object(&format!("{}.gz", index), &file, "data/binary")
-                .unwrap();
-            if code != 200 {
-                println!("Error putting file to S3: {}", code);
-                process::exit(1);
-            } else {
-                println!("Success. Removing {} files.", index);
-                fs::remove_file(format!("{}.gz", index));
-                fs::remove_file(format!("{}.json", index));
+    async fn send_file(
+        sink: &mut Box<dyn Sink>,
+        path: &Path,
+        name: &str,
+        compression: Option<&str>,
+    ) -> Result<Stored, DumpError> {
+        // A chunk at a time, parts can be far bigger than memory
+        let mut file = fs::File::open(path)?;
+        let mut buffer = vec![0; 1 << 20];
+        sink.create(name, compression).await?;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
             }
+            sink.write(&buffer[..read]).await?;
         }
+        sink.flush().await
+    }
 
-        Ok(())
+    async fn mapped_schema(
+        &self,
+        report: &DumpReport,
+        work: &WorkDir,
+        offline: bool,
+    ) -> Result<Option<MappedSchema>, DumpError> {
+        if !matches!(
+            self.format,
+            OutputFormat::Parquet { .. } | OutputFormat::Avro { .. }
+        ) {
+            return Ok(None);
+        }
+
+        // The mapping can't say which fields hold arrays, so look at the documents first.
+        // Without a cluster the documents are all there is to go on.
+        let mapping = match offline {
+            true => None,
+            false => {
+                let path = format!("/{}/_mapping", report.index);
+                let mapping: JsonValue =
+                    serde_json::from_str(&self.request(Method::GET, &path, None).await?)?;
+                Some(match self.source_filter.is_empty() {
+                    true => mapping,
+                    false => self.source_filter.project_mapping(&mapping),
+                })
+            }
+        };
+
+        let mut lists = HashSet::new();
+        let mut properties = Map::new();
+        for slice in &report.slices {
+            let path = work.slice_file(slice.slice_id);
+            if !path.exists() {
+                continue;
+            }
+            // Sources are every other line, after their action line
+            for line in BufReader::new(fs::File::open(&path)?)
+                .lines()
+                .skip(1)
+                .step_by(2)
+            {
+                if let Ok(source) = serde_json::from_str(&line?) {
+                    let source = columnar::expand_dots(source);
+                    columnar::array_paths(&source, "", &mut lists);
+                    if mapping.is_none() {
+                        columnar::infer_properties(&source, &mut properties);
+                    }
+                }
+            }
+        }
+        let mapping = mapping.unwrap_or_else(
+            || json!({ (report.index.clone()): { "mappings": { "properties": properties } } }),
+        );
+        Ok(Some(match self.format {
+            OutputFormat::Avro { nullable, .. } => MappedSchema::Avro(Arc::new(
+                avro::schema_from_mapping(&report.index, &mapping, &lists, nullable),
+            )),
+            _ => MappedSchema::Parquet(Arc::new(columnar::schema_from_mapping(&mapping, &lists))),
+        }))
     }
 
-    async fn consolidate_files(index: &str) -> Result<(), IOError> {
-        // Finds all individual files and cats them together in non-deterministic order
-        let current_dir = env::current_dir()?;
-        let destination_file = format!("{}.json", index);
-        let new_file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&destination_file)
-            .expect("Unable to open consolidation file.");
-
-        let cat_writer = BufWriter::new(new_file);
-        let writer_mutex = Arc::new(Mutex::new(cat_writer));
-
-        // We know all temp files are written in current dir
-        let mut work = vec![];
-        for entry in fs::read_dir(current_dir)? {
-            let entry = entry?;
-            let path = entry.path().to_str().unwrap_or("").to_string();
-            if path.contains(index) && !path.contains(&destination_file) {
-                println!("Consolidating {:?}", entry);
-                work.push(Dumper::buffered_write(path, writer_mutex.clone()));
+    fn create_work_dir(&self, index: &str) -> Result<WorkDir, IOError> {
+        // Slice files live next to the output unless told otherwise
+        WorkDir::create(self.work_dir.as_ref().unwrap_or(&self.output_dir), index)
+    }
+
+    async fn write_manifest(
+        &self,
+        report: &DumpReport,
+        artifacts: Vec<Artifact>,
+        sink: &mut Box<dyn Sink>,
+        origin: Option<ClusterInfo>,
+    ) -> Result<(), DumpError> {
+        // A Source's documents say where they came from, the query and source filter weren't used
+        let (cluster, query, source_filter) = match origin {
+            Some(cluster) => (cluster, None, None),
+            None => (
+                self.cluster_info().await?,
+                self.query.clone(),
+                Some(self.source_filter.clone()).filter(|filter| !filter.is_empty()),
+            ),
+        };
+        let manifest = Manifest {
+            format_version: manifest::FORMAT_VERSION,
+            tana_version: env!("CARGO_PKG_VERSION").to_string(),
+            cluster,
+            index: report.index.clone(),
+            query,
+            source_filter,
+            transforms: self.transforms.as_ref().clone(),
+            redactions: report.redactions.clone(),
+            script: self.script.as_ref().map(|script| script.info().clone()),
+            slices: report.slices.len() as i64,
+            documents: report.documents(),
+            complete: report.is_complete(),
+            started_at: report.started_at,
+            finished_at: Utc::now(),
+            format: self.format.clone(),
+            artifacts,
+        };
+
+        let content = serde_json::to_vec_pretty(&manifest)?;
+        sink.finish(&Manifest::filename(&report.index), &content)
+            .await
+    }
+
+    async fn scroll_slices(
+        &self,
+        index: &str,
+        slices: i64,
+        work: &WorkDir,
+    ) -> Result<DumpReport, DumpError> {
+        // Create a lot of work for ourselves
+        //   They each need their own copy of the url, credentials and index
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let options = self
+            .index_scroll_options
+            .get(index)
+            .unwrap_or(&self.scroll_options);
+        options.validate()?;
+        let redactor = Arc::new(self.redactor.counted());
+        let mut scrolls = vec![];
+        if self.shard_routing {
+            // Each slice reads its own shards straight from a node holding them
+            for route in self.shard_routes(index, slices).await? {
+                println!("Creating scroller {}", route);
+                let scroller = Scroller::new(
+                    self.client.clone(),
+                    self.hosts.clone(),
+                    index.to_string(),
+                    route.slice_id,
+                    1,
+                    options.clone(),
+                    self.cancel.clone(),
+                )
+                .with_query(self.query.clone())
+                .with_route(&route)
+                .with_source_filter(&self.source_filter)
+                .with_transforms(self.transforms.clone())
+                .with_redactor(redactor.clone())
+                .with_script(self.script.clone());
+                scrolls.push(scroller.scroll(self.creds.clone(), work.slice_file(route.slice_id)));
+            }
+        } else {
+            for i in 0..slices {
+                println!("Creating scroller {}", i);
+                let scroller = Scroller::new(
+                    self.client.clone(),
+                    self.hosts.clone(),
+                    index.to_string(),
+                    i,
+                    slices,
+                    options.clone(),
+                    self.cancel.clone(),
+                )
+                .with_query(self.query.clone())
+                .with_source_filter(&self.source_filter)
+                .with_transforms(self.transforms.clone())
+                .with_redactor(redactor.clone())
+                .with_script(self.script.clone());
+                scrolls.push(scroller.scroll(self.creds.clone(), work.slice_file(i)));
             }
         }
 
-        // Wait for workers to return
-        join_all(work).await;
+        // Wait for that work to finish
+        let slices = join_all(scrolls).await;
 
-        Ok(())
+        let report = DumpReport {
+            index: index.to_string(),
+            slices,
+            started_at,
+            duration: started.elapsed(),
+            reconciliation: None,
+            redactions: redactor.applied(),
+        };
+        if self.cancel.is_cancelled() {
+            // Nothing half-written is worth keeping, even with allow_partial
+            Dumper::discard_slices(work);
+            return Err(DumpError::Cancelled(Box::new(report)));
+        }
+        Ok(report)
     }
 
-    async fn compress_final(index: String) -> Result<(), IOError> {
-        let in_file = fs::File::open(format!("{}.json", index))
-            .expect("Unable to open uncompressed JSON file.");
-        let mut out_file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(format!("{}.gz", index))
-            .expect("Unable to open target compressed file.");
-
-        let reader = BufReader::new(in_file);
-        let z_stream = GzEncoder::new(reader, Compression::best()).bytes();
-        println!("Compressing final result...");
-        for byte in z_stream.map(|b| b.unwrap()) {
-            out_file.write(&vec![byte])?;
+    async fn check_report(
+        &self,
+        report: DumpReport,
+        work: &WorkDir,
+    ) -> Result<DumpReport, DumpError> {
+        if !report.is_complete() {
+            return self.keep_partial(report, work);
         }
 
-        Ok(())
+        // Compare what we wrote against what the cluster says is there
+        let expected = self.count(&report.index).await?;
+        self.reconcile(report, work, expected, "_count")
+    }
+
+    fn keep_partial(&self, report: DumpReport, work: &WorkDir) -> Result<DumpReport, DumpError> {
+        if self.allow_partial {
+            return Ok(report);
+        }
+        Dumper::discard_slices(work);
+        Err(DumpError::Incomplete(Box::new(report)))
     }
 
-    async fn buffered_write(
-        path: String,
-        writer_mutex: Arc<Mutex<BufWriter<fs::File>>>,
-    ) -> Result<(), IOError> {
-        // Takes a writer object and writes entries from a BufReader
-        let in_file = fs::File::open(&path).expect("Unable to open file.");
-        let reader = BufReader::new(in_file);
-        for line in reader.lines() {
-            // Obtain the lock and write
-            let mut new_line = line?;
-            new_line.push('\n');
-            let mut writer = writer_mutex.lock().expect("Cannot obtain lock for writer.");
-            writer.write(&new_line.as_bytes())?;
+    fn reconcile(
+        &self,
+        mut report: DumpReport,
+        work: &WorkDir,
+        expected: u64,
+        expected_from: &str,
+    ) -> Result<DumpReport, DumpError> {
+        let paths: Vec<PathBuf> = report
+            .slices
+            .iter()
+            .map(|slice| work.slice_file(slice.slice_id))
+            .filter(|path| path.exists())
+            .collect();
+        let duplicate_ids = if self.verify_ids {
+            Some(reconcile::count_duplicate_ids(&paths)?)
+        } else {
+            None
+        };
+        let reconciliation = Reconciliation {
+            expected,
+            expected_from: expected_from.to_string(),
+            written: report.documents(),
+            dropped: report.dropped(),
+            duplicate_ids,
+        };
+        println!("Reconciliation: {}", reconciliation);
+
+        let reconciled = reconciliation.is_ok();
+        report.reconciliation = Some(reconciliation);
+        if !reconciled {
+            Dumper::discard_slices(work);
+            return Err(DumpError::Mismatch(Box::new(report)));
         }
+        Ok(report)
+    }
 
-        fs::remove_file(path)?;
+    fn discard_slices(work: &WorkDir) {
+        // Don't leave partial slices lying around
+        if let Err(e) = work.remove() {
+            println!("Unable to remove {}: {}", work.path().display(), e);
+        }
+    }
+
+    async fn consolidate_files(
+        report: &mut DumpReport,
+        work: &WorkDir,
+        parts: &mut PartitionWriter,
+        allow_partial: bool,
+    ) -> Result<(), DumpError> {
+        // Whole slices in slice-id order, so the same slices always make the same bytes
+        let mut order: Vec<usize> = (0..report.slices.len()).collect();
+        order.sort_unstable_by_key(|&i| report.slices[i].slice_id);
+
+        // Only the slices this run wrote, a failed slice may have left no file
+        for i in order {
+            let slice_id = report.slices[i].slice_id;
+            let path = work.slice_file(slice_id);
+            if !path.exists() {
+                continue;
+            }
+            println!("Consolidating {}", path.display());
+            let reader = BufReader::new(fs::File::open(&path)?);
+            let (documents, torn) = Dumper::copy_documents(reader, parts)?;
+            let slice = &mut report.slices[i];
+            if torn {
+                // A slice that finished never ends part way through a document, its file is damaged
+                if slice.is_ok() {
+                    slice.status = SliceStatus::Failed(
+                        "Slice file ends with a half-written document".to_string(),
+                    );
+                }
+                if !allow_partial {
+                    return Err(DumpError::Incomplete(Box::new(report.clone())));
+                }
+                println!(
+                    "Dropped a half-written document at the end of slice {}.",
+                    slice_id
+                );
+            }
+            // What actually went into the output, so the manifest agrees with it
+            slice.documents = documents;
+        }
+        if let Some(reconciliation) = &mut report.reconciliation {
+            reconciliation.written = report.slices.iter().map(|slice| slice.documents).sum();
+        }
 
+        work.remove()?;
         Ok(())
     }
+
+    fn copy_documents<R: BufRead>(
+        mut reader: R,
+        parts: &mut PartitionWriter,
+    ) -> Result<(u64, bool), IOError> {
+        // Action and source lines only ever go out together, true if a torn tail was left behind
+        let (mut action, mut source) = (String::new(), String::new());
+        let mut documents = 0;
+        loop {
+            action.clear();
+            source.clear();
+            if reader.read_line(&mut action)? == 0 {
+                return Ok((documents, false));
+            }
+            reader.read_line(&mut source)?;
+            if !action.ends_with('\n') || !source.ends_with('\n') {
+                return Ok((documents, true));
+            }
+            parts.write_document(&action, &source)?;
+            documents += 1;
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
     #[tokio::test]
     async fn correct_formatting() {
-        let created = Dumper::new_with_bucket(
-            "https://localhost:9200",
-            "elastic",
-            "test-account",
-            "dummy-bucket",
-            "FAKE",
-            "NOSECRETS",
-            "eu-west-1",
-        );
+        // Needs a cluster with a logs index, the rest of the tests run without one
+        let host = match std::env::var("TANA_TEST_ES_HOST") {
+            Ok(host) => host,
+            Err(_) => {
+                println!("TANA_TEST_ES_HOST is not set, skipping correct_formatting");
+                return;
+            }
+        };
+        let created = Dumper::new(&host, "elastic", "test-account");
         // Get the indices
-        let elastic_dumper = created.unwrap();
+        let output = std::env::temp_dir().join(format!("tana-formatting-{}", std::process::id()));
+        let elastic_dumper = created.unwrap().output_dir(&output);
         // Consume and dump to disk without compression
         let slices: i64 = 3;
-        let dumped = elastic_dumper
+        let report = elastic_dumper
             .dump_index_to_disk("logs".to_string(), slices)
-            .await;
-        let file = std::fs::File::open("logs.json").unwrap();
+            .await
+            .unwrap();
+        assert!(report.is_complete());
+        let file = std::fs::File::open(output.join("logs.json")).unwrap();
         let reader = std::io::BufReader::new(file);
         verify_lines(reader);
+        fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_unbounded_parts_for_s3() {
+        let credentials = S3Credentials::new(
+            Some("FAKE".to_string()),
+            Some("NOSECRETS".to_string()),
+            None,
+            None,
+        );
+        let bucket =
+            S3Bucket::new("dummy-bucket", "eu-west-1".parse().unwrap(), credentials).unwrap();
+        let s3 = || Box::new(GzipSink::new(S3Sink::new(bucket.clone())));
+
+        // Refused before anything is scrolled, so no cluster is needed to see it
+        let dumper = Dumper::new("http://localhost:9200", "", "").unwrap();
+        let dumped = dumper.sink(s3()).dump_index("logs".to_string(), 1).await;
+        assert!(matches!(dumped, Err(DumpError::Config(_))));
+
+        let limits = PartLimits {
+            max_documents: Some(1000),
+            max_bytes: None,
+        };
+        let dumper = Dumper::new("http://localhost:9200", "", "").unwrap();
+        let dumper = dumper.sink(s3()).part_limits(limits);
+        assert!(dumper.check_sink().is_err());
+        let dumper = dumper.part_limits(PartLimits {
+            max_documents: None,
+            max_bytes: Some(1 << 20),
+        });
+        assert!(dumper.check_sink().is_ok());
+    }
+
+    #[tokio::test]
+    async fn consolidates_whole_documents_in_slice_order() {
+        let parent = std::env::temp_dir();
+        let work = WorkDir::create(&parent, "consolidate-test").unwrap();
+        fs::write(work.slice_file(0), "{\"a\":0}\n{}\n").unwrap();
+        fs::write(work.slice_file(1), "{\"a\":1}\n{}\n{\"a\":2}\n{\"tor").unwrap();
+        fs::write(work.slice_file(2), "{\"a\":3}\n{}\n").unwrap();
+        let mut report = DumpReport {
+            index: "consolidate-test".to_string(),
+            slices: [(2, 1), (0, 1), (1, 2)]
+                .iter()
+                .map(|(id, documents)| {
+                    SliceReport::new(*id, Ok((*documents, 1, 0)), Duration::new(0, 0))
+                })
+                .collect(),
+            started_at: Utc::now(),
+            duration: Duration::new(0, 0),
+            reconciliation: None,
+            redactions: vec![],
+        };
+        let parts = |staging: &WorkDir| {
+            PartitionWriter::new(
+                staging.path(),
+                "consolidate-test",
+                Partitioning::None,
+                OutputFormat::Bulk,
+                None,
+                PartLimits::default(),
+            )
+        };
+
+        // Slice 1 says it finished, so its torn tail is damage rather than a failure to keep
+        {
+            let staging = WorkDir::create(&parent, "consolidate-test-parts").unwrap();
+            let mut refused = report.clone();
+            let consolidated =
+                Dumper::consolidate_files(&mut refused, &work, &mut parts(&staging), false).await;
+            assert!(matches!(consolidated, Err(DumpError::Incomplete(_))));
+        }
+
+        let staging = WorkDir::create(&parent, "consolidate-test-parts").unwrap();
+        let mut kept = parts(&staging);
+        Dumper::consolidate_files(&mut report, &work, &mut kept, true)
+            .await
+            .unwrap();
+        let destination = &kept.finish().unwrap()[0].path;
+        let consolidated = fs::read_to_string(destination).unwrap();
+
+        assert_eq!(
+            consolidated,
+            "{\"a\":0}\n{}\n{\"a\":1}\n{}\n{\"a\":3}\n{}\n"
+        );
+        assert_eq!(report.documents(), 3);
+        assert!(!report.is_complete());
+        assert!(!work.path().exists());
     }
 
     fn verify_lines(reader: BufReader<fs::File>) {
         // Verify every other line contains "index" and every other line contains "@timestamp"
-        let mut i: i32 = 0;
-        for line in reader.lines() {
+        for (i, line) in reader.lines().enumerate() {
             let text = line.unwrap();
             if i % 2 == 0 {
                 if !text.contains("index") {
                     println!("Failing line {}", i);
                     assert!(text.contains("index"));
                 }
-            } else {
-                if !text.contains("@timestamp") {
-                    println!("Failing line {}", i);
-                    assert!(text.contains("@timestamp"));
-                }
+            } else if !text.contains("@timestamp") {
+                println!("Failing line {}", i);
+                assert!(text.contains("@timestamp"));
             }
-            i += 1;
         }
     }
 }
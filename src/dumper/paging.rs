@@ -0,0 +1,102 @@
+use crate::dumper::error::DumpError;
+use std::time::Duration;
+
+// search_after can't page past index.max_result_window, 10000 by default
+const MAX_PAGE_SIZE: u64 = 10000;
+const MIN_PAGE_SIZE: u64 = 10;
+// Give up once shrinking stops helping
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+
+#[derive(Debug, Clone)]
+pub struct ScrollOptions {
+    // Hits per page
+    pub page_size: u64,
+    // How long Elasticsearch keeps the scroll or point in time alive between pages
+    pub keep_alive: String,
+    // Page with a point in time so the page size can change between requests
+    pub adaptive: bool,
+}
+
+impl Default for ScrollOptions {
+    fn default() -> ScrollOptions {
+        ScrollOptions {
+            page_size: 1000,
+            keep_alive: "1m".to_string(),
+            adaptive: false,
+        }
+    }
+}
+
+impl ScrollOptions {
+    pub fn validate(&self) -> Result<(), DumpError> {
+        // Caught here rather than by the cluster once every slice has started
+        if !(1..=MAX_PAGE_SIZE).contains(&self.page_size) {
+            return Err(DumpError::Config(format!(
+                "The page size must be from 1 to {} documents",
+                MAX_PAGE_SIZE
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub struct AdaptivePageSize {
+    current: u64,
+    failures: u32,
+    // Pages quicker than this grow the next one
+    fast: Duration,
+}
+
+impl AdaptivePageSize {
+    pub fn new(page_size: u64) -> AdaptivePageSize {
+        AdaptivePageSize {
+            current: page_size.clamp(MIN_PAGE_SIZE, MAX_PAGE_SIZE),
+            failures: 0,
+            fast: Duration::from_secs(1),
+        }
+    }
+
+    pub fn current(&self) -> u64 {
+        self.current
+    }
+
+    pub fn succeeded(&mut self, took: Duration) {
+        self.failures = 0;
+        if took < self.fast {
+            self.current = (self.current + self.current / 2).min(MAX_PAGE_SIZE);
+        }
+    }
+
+    pub fn failed(&mut self) -> bool {
+        // Halve and try again, false once we should stop retrying
+        self.failures += 1;
+        self.current = (self.current / 2).max(MIN_PAGE_SIZE);
+        self.failures < MAX_CONSECUTIVE_FAILURES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shrinks_on_failure_and_grows_when_fast() {
+        let mut page_size = AdaptivePageSize::new(1000);
+        assert!(page_size.failed());
+        assert_eq!(page_size.current(), 500);
+
+        page_size.succeeded(Duration::from_secs(5));
+        assert_eq!(page_size.current(), 500);
+        page_size.succeeded(Duration::from_millis(100));
+        assert_eq!(page_size.current(), 750);
+
+        for _ in 0..4 {
+            assert!(page_size.failed());
+        }
+        assert!(!page_size.failed());
+
+        let mut page_size = AdaptivePageSize::new(15);
+        page_size.failed();
+        assert_eq!(page_size.current(), MIN_PAGE_SIZE);
+    }
+}
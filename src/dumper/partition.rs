@@ -0,0 +1,241 @@
+use crate::dumper::format::OutputFormat;
+use crate::dumper::parts::{MappedSchema, Part, PartLimits, PartWriter};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde_json::Value as JsonValue;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+
+// High cardinality fields would otherwise hold a file handle per value
+const MAX_OPEN_PARTITIONS: usize = 64;
+// Documents without the field still need somewhere to go
+const MISSING: &str = "_missing";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Partitioning {
+    #[default]
+    None,
+    // By the UTC day or hour of a timestamp field, e.g. date=2020-04-20/hour=13
+    Day(String),
+    Hour(String),
+    // By the value of a keyword field, e.g. host.name=web-1
+    Field(String),
+}
+
+impl Partitioning {
+    pub fn partition(&self, source: &JsonValue) -> Option<String> {
+        // Where a document goes, relative to the index's directory
+        let (field, hourly) = match self {
+            Partitioning::None => return None,
+            Partitioning::Field(field) => {
+                let value = match field_value(source, field) {
+                    Some(JsonValue::String(value)) => sanitize(value),
+                    Some(JsonValue::Null) | None => MISSING.to_string(),
+                    Some(value) => sanitize(&value.to_string()),
+                };
+                return Some(format!("{}={}", field, value));
+            }
+            Partitioning::Day(field) => (field, false),
+            Partitioning::Hour(field) => (field, true),
+        };
+
+        Some(match field_value(source, field).and_then(timestamp) {
+            Some(at) if hourly => at.format("date=%Y-%m-%d/hour=%H").to_string(),
+            Some(at) => at.format("date=%Y-%m-%d").to_string(),
+            None => format!("date={}", MISSING),
+        })
+    }
+}
+
+pub struct PartitionWriter {
+    dir: PathBuf,
+    index: String,
+    partitioning: Partitioning,
+    format: OutputFormat,
+    schema: Option<MappedSchema>,
+    limits: PartLimits,
+    writers: BTreeMap<String, PartWriter>,
+}
+
+impl PartitionWriter {
+    pub fn new(
+        dir: &Path,
+        index: &str,
+        partitioning: Partitioning,
+        format: OutputFormat,
+        schema: Option<MappedSchema>,
+        limits: PartLimits,
+    ) -> PartitionWriter {
+        PartitionWriter {
+            dir: dir.to_path_buf(),
+            index: index.to_string(),
+            partitioning,
+            format,
+            schema,
+            limits,
+            writers: BTreeMap::new(),
+        }
+    }
+
+    pub fn write_document(&mut self, action: &str, source: &str) -> Result<(), IOError> {
+        let (action, output) = take_output(action)?;
+        let mut partition = match self.partitioning {
+            Partitioning::None => String::new(),
+            _ => {
+                let parsed: JsonValue = serde_json::from_str(source)?;
+                self.partitioning.partition(&parsed).unwrap_or_default()
+            }
+        };
+        if let Some(output) = output {
+            // A routed document gets its own tree, partitioned the same way inside it
+            partition = match partition.is_empty() {
+                true => format!("output={}", sanitize(&output)),
+                false => format!("output={}/{}", sanitize(&output), partition),
+            };
+        }
+
+        if !self.writers.contains_key(&partition) {
+            let (dir, index) = if partition.is_empty() {
+                (self.dir.clone(), Some(self.index.as_str()))
+            } else {
+                (self.dir.join(&self.index).join(&partition), None)
+            };
+            let writer = PartWriter::new(
+                &dir,
+                index,
+                self.format.clone(),
+                self.schema.clone(),
+                self.limits.clone(),
+            );
+            self.writers.insert(partition.clone(), writer);
+        }
+        let open = self
+            .writers
+            .values()
+            .filter(|writer| writer.is_open())
+            .count();
+        if open >= MAX_OPEN_PARTITIONS && !self.writers[&partition].is_open() {
+            for writer in self.writers.values_mut() {
+                writer.suspend()?;
+            }
+        }
+
+        self.writers
+            .get_mut(&partition)
+            .expect("Inserted above")
+            .write_document(&action, source)
+    }
+
+    pub fn finish(self) -> Result<Vec<Part>, IOError> {
+        // Partitions in name order, so the manifest lists them the same way every time
+        if self.writers.is_empty() && self.partitioning == Partitioning::None {
+            let index = Some(self.index.as_str());
+            return PartWriter::new(&self.dir, index, self.format, self.schema, self.limits)
+                .finish();
+        }
+        let mut parts = vec![];
+        for (_, writer) in self.writers {
+            parts.extend(writer.finish()?);
+        }
+        Ok(parts)
+    }
+}
+
+fn take_output(action: &str) -> Result<(Cow<'_, str>, Option<String>), IOError> {
+    // Set by a script in the slice file, bulk would refuse it so it never reaches a part
+    if !action.contains("\"output\"") {
+        return Ok((Cow::Borrowed(action), None));
+    }
+    let mut parsed: JsonValue = serde_json::from_str(action)?;
+    let output = match parsed
+        .as_object_mut()
+        .and_then(|object| object.remove("output"))
+    {
+        Some(JsonValue::String(output)) => output,
+        _ => return Ok((Cow::Borrowed(action), None)),
+    };
+    Ok((Cow::Owned(format!("{}\n", parsed)), Some(output)))
+}
+
+pub fn field_value<'a>(source: &'a JsonValue, field: &str) -> Option<&'a JsonValue> {
+    // Either a literal dotted key or a path through objects
+    if let Some(value) = source.get(field) {
+        return Some(value);
+    }
+    field
+        .split('.')
+        .try_fold(source, |value, key| value.get(key))
+}
+
+pub fn timestamp(value: &JsonValue) -> Option<DateTime<Utc>> {
+    // Dates as Elasticsearch returns them: RFC 3339, a bare date or epoch millis
+    match value {
+        JsonValue::Number(millis) => Utc.timestamp_millis_opt(millis.as_i64()?).single(),
+        JsonValue::String(text) => DateTime::parse_from_rfc3339(text)
+            .map(|at| at.with_timezone(&Utc))
+            .ok()
+            .or_else(|| {
+                let date = NaiveDate::parse_from_str(text.get(..10)?, "%Y-%m-%d").ok()?;
+                Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+            }),
+        _ => None,
+    }
+}
+
+fn sanitize(value: &str) -> String {
+    // Keep partition values safe as both a directory and an S3 key
+    let value: String = value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if value.is_empty() || value.chars().all(|c| c == '.') {
+        return MISSING.to_string();
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn partitions_by_date_or_field() {
+        let source = json!({
+            "@timestamp": "2020-04-20T13:45:00+02:00",
+            "host": { "name": "web/1" },
+            "event.dataset": "nginx.access",
+        });
+
+        let day = Partitioning::Day("@timestamp".to_string());
+        assert_eq!(day.partition(&source).unwrap(), "date=2020-04-20");
+        let hour = Partitioning::Hour("@timestamp".to_string());
+        assert_eq!(hour.partition(&source).unwrap(), "date=2020-04-20/hour=11");
+        let hour = Partitioning::Hour("created".to_string());
+        assert_eq!(
+            hour.partition(&json!({ "created": 1587390300000u64 }))
+                .unwrap(),
+            "date=2020-04-20/hour=13"
+        );
+
+        let host = Partitioning::Field("host.name".to_string());
+        assert_eq!(host.partition(&source).unwrap(), "host.name=web_1");
+        let dataset = Partitioning::Field("event.dataset".to_string());
+        assert_eq!(
+            dataset.partition(&source).unwrap(),
+            "event.dataset=nginx.access"
+        );
+        assert_eq!(
+            dataset.partition(&json!({})).unwrap(),
+            "event.dataset=_missing"
+        );
+        assert_eq!(Partitioning::None.partition(&source), None);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::dumper::avro::{AvroFile, AvroType};
+use crate::dumper::columnar::ParquetFile;
+use crate::dumper::format::OutputFormat;
+use arrow::datatypes::SchemaRef;
+use serde_json::{json, Value as JsonValue};
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+pub struct PartLimits {
+    // Start a new part once the current one holds this many documents
+    pub max_documents: Option<u64>,
+    // ...or before it would grow past this many bytes, measured before compression
+    pub max_bytes: Option<u64>,
+}
+
+impl PartLimits {
+    pub fn is_bounded(&self) -> bool {
+        self.max_documents.is_some() || self.max_bytes.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub path: PathBuf,
+    pub documents: u64,
+    pub bytes: u64,
+    // Documents that didn't fit the schema, as NDJSON
+    pub dead_letter: bool,
+}
+
+#[derive(Clone)]
+pub enum MappedSchema {
+    // The columns or record every part is written with, from the index mapping
+    Parquet(SchemaRef),
+    Avro(Arc<AvroType>),
+}
+
+enum PartFile {
+    Lines(BufWriter<fs::File>),
+    Parquet(Box<ParquetFile>),
+    Avro(AvroFile),
+}
+
+impl PartFile {
+    fn close(self) -> Result<(), IOError> {
+        match self {
+            PartFile::Lines(mut writer) => writer.flush(),
+            PartFile::Parquet(file) => file.close(),
+            PartFile::Avro(file) => file.close(),
+        }
+    }
+}
+
+pub struct PartWriter {
+    dir: PathBuf,
+    // None inside a partition, where parts are just part-00000.json
+    index: Option<String>,
+    format: OutputFormat,
+    schema: Option<MappedSchema>,
+    limits: PartLimits,
+    file: Option<PartFile>,
+    parts: Vec<Part>,
+    dead_letter: Option<BufWriter<fs::File>>,
+    dead_part: Option<Part>,
+}
+
+impl PartWriter {
+    pub fn new(
+        dir: &Path,
+        index: Option<&str>,
+        format: OutputFormat,
+        schema: Option<MappedSchema>,
+        limits: PartLimits,
+    ) -> PartWriter {
+        PartWriter {
+            dir: dir.to_path_buf(),
+            index: index.map(|index| index.to_string()),
+            format,
+            schema,
+            limits,
+            file: None,
+            parts: vec![],
+            dead_letter: None,
+            dead_part: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub fn suspend(&mut self) -> Result<(), IOError> {
+        // Give the file handle back, the next document reopens the part where it left off.
+        // A Parquet or Avro part can't be reopened once it's closed, so it gets a new part.
+        if let Some(mut dead_letter) = self.dead_letter.take() {
+            dead_letter.flush()?;
+        }
+        match self.file.take() {
+            Some(file) => file.close(),
+            None => Ok(()),
+        }
+    }
+
+    fn part_name(&self, part: usize) -> String {
+        // An unbounded dump keeps the single {index}.json it always had
+        let extension = self.format.extension();
+        match (&self.index, self.limits.is_bounded()) {
+            (Some(index), false) => format!("{}.{}", index, extension),
+            (Some(index), true) => format!("{}.part-{:05}.{}", index, part, extension),
+            (None, _) => format!("part-{:05}.{}", part, extension),
+        }
+    }
+
+    pub fn write_document(&mut self, action: &str, source: &str) -> Result<(), IOError> {
+        // A document never straddles two parts, one bigger than max_bytes gets a part to itself
+        let rendered = match self.format {
+            OutputFormat::Parquet { .. } | OutputFormat::Avro { .. } => None,
+            _ => Some(self.format.render(action, source)?),
+        };
+        let bytes = match &rendered {
+            Some(rendered) => rendered.len() as u64,
+            None => (action.len() + source.len()) as u64,
+        };
+        let sealed = self.file.is_none() && rendered.is_none();
+        if self.parts.is_empty() || sealed || self.is_full(bytes) {
+            self.rotate()?;
+        } else if self.file.is_none() {
+            let part = self.parts.last().expect("Checked above");
+            let file = fs::OpenOptions::new().append(true).open(&part.path)?;
+            self.file = Some(PartFile::Lines(BufWriter::new(file)));
+        }
+
+        match (self.file.as_mut(), rendered) {
+            (Some(PartFile::Lines(writer)), Some(rendered)) => {
+                writer.write_all(rendered.as_bytes())?
+            }
+            (Some(PartFile::Parquet(file)), None) => {
+                if let Some(reason) = file.write_document(action, source)? {
+                    return self.reject(action, source, reason);
+                }
+            }
+            (Some(PartFile::Avro(file)), None) => {
+                if let Some(reason) = file.write_document(action, source)? {
+                    return self.reject(action, source, reason);
+                }
+            }
+            _ => unreachable!("A part of the right kind is always open here"),
+        }
+        let part = self.parts.last_mut().expect("A part is always open here");
+        part.documents += 1;
+        part.bytes += bytes;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Vec<Part>, IOError> {
+        // Even an empty dump has one (empty) part to describe
+        if self.parts.is_empty() {
+            self.rotate()?;
+        }
+        self.suspend()?;
+        self.parts.extend(self.dead_part);
+        Ok(self.parts)
+    }
+
+    fn reject(&mut self, action: &str, source: &str, reason: String) -> Result<(), IOError> {
+        // Kept next to the parts with why it was turned away, rather than dropped
+        let action: JsonValue = serde_json::from_str(action)?;
+        let source: JsonValue = serde_json::from_str(source)?;
+        let line = json!({
+            "_index": action["index"]["_index"],
+            "_id": action["index"]["_id"],
+            "error": reason,
+            "_source": source,
+        });
+
+        if self.dead_letter.is_none() {
+            let file = match &self.dead_part {
+                Some(part) => fs::OpenOptions::new().append(true).open(&part.path)?,
+                None => {
+                    let name = match &self.index {
+                        Some(index) => format!("{}.dead-letter.ndjson", index),
+                        None => "dead-letter.ndjson".to_string(),
+                    };
+                    let path = self.dir.join(name);
+                    let file = fs::File::create(&path)?;
+                    self.dead_part = Some(Part {
+                        path,
+                        documents: 0,
+                        bytes: 0,
+                        dead_letter: true,
+                    });
+                    file
+                }
+            };
+            self.dead_letter = Some(BufWriter::new(file));
+        }
+        let line = format!("{}\n", line);
+        self.dead_letter
+            .as_mut()
+            .expect("Opened above")
+            .write_all(line.as_bytes())?;
+        let part = self.dead_part.as_mut().expect("Created above");
+        part.documents += 1;
+        part.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    fn is_full(&self, bytes: u64) -> bool {
+        let part = match self.parts.last() {
+            Some(part) if part.documents > 0 => part,
+            _ => return false,
+        };
+        self.limits
+            .max_documents
+            .is_some_and(|max| part.documents >= max)
+            || self
+                .limits
+                .max_bytes
+                .is_some_and(|max| part.bytes + bytes > max)
+    }
+
+    fn rotate(&mut self) -> Result<(), IOError> {
+        self.suspend()?;
+
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(self.part_name(self.parts.len()));
+        let mut file = match (&self.format, &self.schema) {
+            (
+                OutputFormat::Parquet {
+                    compression,
+                    row_group_size,
+                },
+                Some(MappedSchema::Parquet(schema)),
+            ) => PartFile::Parquet(Box::new(ParquetFile::create(
+                &path,
+                schema.clone(),
+                *compression,
+                *row_group_size,
+            )?)),
+            (OutputFormat::Avro { codec, .. }, Some(MappedSchema::Avro(schema))) => {
+                PartFile::Avro(AvroFile::create(&path, schema.clone(), *codec)?)
+            }
+            _ => PartFile::Lines(BufWriter::new(fs::File::create(&path)?)),
+        };
+        let mut part = Part {
+            path,
+            documents: 0,
+            bytes: 0,
+            dead_letter: false,
+        };
+        if let (PartFile::Lines(writer), Some(header)) = (&mut file, self.format.header()?) {
+            writer.write_all(header.as_bytes())?;
+            part.bytes += header.len() as u64;
+        }
+        self.file = Some(file);
+        self.parts.push(part);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_on_documents_or_bytes() {
+        let dir = std::env::temp_dir().join(format!("tana-parts-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let limits = PartLimits {
+            max_documents: Some(2),
+            max_bytes: Some(40),
+        };
+        let mut parts = PartWriter::new(&dir, Some("logs"), OutputFormat::Bulk, None, limits);
+        for source in &["{}\n", "{}\n", "{}\n", "{\"big\":\"0123456789\"}\n", "{}\n"] {
+            parts.write_document("{\"index\":{}}\n", source).unwrap();
+        }
+        let parts = parts.finish().unwrap();
+
+        let documents: Vec<u64> = parts.iter().map(|part| part.documents).collect();
+        assert_eq!(documents, vec![2, 1, 1, 1]);
+        assert!(parts[0].path.ends_with("logs.part-00000.json"));
+        assert_eq!(
+            fs::read_to_string(&parts[3].path).unwrap(),
+            "{\"index\":{}}\n{}\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+
+// Descriptors kept back for everything that isn't a slice
+const RESERVED_FILE_HANDLES: u64 = 64;
+// A slice holds its temp file and a connection or two
+const FILE_HANDLES_PER_SLICE: u64 = 4;
+
+#[derive(Debug, Clone)]
+pub struct SlicePlan {
+    pub index: String,
+    pub primary_shards: u64,
+    pub budget: u64,
+    // None where we can't ask the OS
+    pub file_handle_limit: Option<u64>,
+    pub slices: i64,
+}
+
+impl SlicePlan {
+    pub fn new(
+        index: &str,
+        primary_shards: u64,
+        budget: u64,
+        file_handle_limit: Option<u64>,
+    ) -> SlicePlan {
+        // One slice per primary shard is what Elasticsearch does best
+        let mut slices = primary_shards.min(budget);
+        if let Some(limit) = file_handle_limit {
+            slices =
+                slices.min(limit.saturating_sub(RESERVED_FILE_HANDLES) / FILE_HANDLES_PER_SLICE);
+        }
+
+        SlicePlan {
+            index: index.to_string(),
+            primary_shards,
+            budget,
+            file_handle_limit,
+            slices: slices.max(1) as i64,
+        }
+    }
+}
+
+impl fmt::Display for SlicePlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Index '{}' has {} primary shards, concurrency budget {}, file handle limit {}: using {} slices",
+            self.index,
+            self.primary_shards,
+            self.budget,
+            self.file_handle_limit
+                .map(|limit| limit.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            self.slices
+        )
+    }
+}
+
+#[cfg(unix)]
+pub fn file_handle_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // The soft limit is the one we'd actually hit
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    // rlim_t isn't u64 everywhere
+    #[allow(clippy::unnecessary_cast)]
+    Some(limit.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+pub fn file_handle_limit() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slices_follow_shards_within_budget() {
+        assert_eq!(SlicePlan::new("logs", 1, 16, None).slices, 1);
+        assert_eq!(SlicePlan::new("logs", 5, 16, None).slices, 5);
+        assert_eq!(SlicePlan::new("logs", 30, 16, Some(4096)).slices, 16);
+        assert_eq!(SlicePlan::new("logs", 0, 16, None).slices, 1);
+
+        // (256 - 64 reserved) / 4 per slice leaves room for 48
+        assert_eq!(SlicePlan::new("logs", 100, 64, Some(256)).slices, 48);
+        assert_eq!(SlicePlan::new("logs", 5, 16, Some(16)).slices, 1);
+    }
+}
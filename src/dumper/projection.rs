@@ -0,0 +1,130 @@
+use crate::dumper::columnar::mapped_properties;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SourceFilter {
+    // Field paths or wildcards, e.g. winlog.*, as Elasticsearch's _source filtering takes them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excludes: Vec<String>,
+}
+
+impl SourceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.includes.is_empty() && self.excludes.is_empty()
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        // For the _source of a search body
+        json!({ "includes": self.includes, "excludes": self.excludes })
+    }
+
+    pub fn keeps(&self, path: &str) -> bool {
+        // A field goes if it or an object above it is excluded, or nothing includes it
+        (self.includes.is_empty() || covers(&self.includes, path)) && !covers(&self.excludes, path)
+    }
+
+    pub fn project_mapping(&self, mapping: &JsonValue) -> JsonValue {
+        // The mapping as the filtered documents see it, so typed formats only get columns that arrive
+        let properties = self.project(&mapped_properties(mapping), "");
+        json!({ "_projected": { "mappings": { "properties": properties } } })
+    }
+
+    fn project(&self, properties: &Map<String, JsonValue>, prefix: &str) -> Map<String, JsonValue> {
+        let mut projected = Map::new();
+        for (name, mapping) in properties {
+            let path = format!("{}{}", prefix, name);
+            if covers(&self.excludes, &path) {
+                continue;
+            }
+            match mapping.get("properties") {
+                // An object stays if it's included whole, or something inside it is
+                Some(JsonValue::Object(children)) if !self.keeps(&path) => {
+                    let children = self.project(children, &format!("{}.", path));
+                    if !children.is_empty() {
+                        let mut mapping = mapping.clone();
+                        mapping["properties"] = JsonValue::Object(children);
+                        projected.insert(name.clone(), mapping);
+                    }
+                }
+                Some(JsonValue::Object(children)) => {
+                    let mut mapping = mapping.clone();
+                    mapping["properties"] =
+                        JsonValue::Object(self.project(children, &format!("{}.", path)));
+                    projected.insert(name.clone(), mapping);
+                }
+                _ if self.keeps(&path) => {
+                    projected.insert(name.clone(), mapping.clone());
+                }
+                _ => {}
+            }
+        }
+        projected
+    }
+}
+
+fn covers(patterns: &[String], path: &str) -> bool {
+    // Matching an object matches everything in it
+    let prefixes: Vec<&str> = path.match_indices('.').map(|(at, _)| &path[..at]).collect();
+    patterns.iter().any(|pattern| {
+        wildcard(pattern, path) || prefixes.iter().any(|prefix| wildcard(pattern, prefix))
+    })
+}
+
+fn wildcard(pattern: &str, text: &str) -> bool {
+    // * matches any run of characters, dots included
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((head, tail)) => {
+            text.starts_with(head)
+                && (head.len()..=text.len())
+                    .filter(|at| text.is_char_boundary(*at))
+                    .any(|at| wildcard(tail, &text[at..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projects_like_source_filtering() {
+        let filter = SourceFilter {
+            includes: vec![
+                "host.name".to_string(),
+                "winlog*".to_string(),
+                "n".to_string(),
+            ],
+            excludes: vec!["winlog.event_data".to_string()],
+        };
+        assert!(filter.keeps("host.name"));
+        assert!(!filter.keeps("host.ip"));
+        assert!(filter.keeps("winlog.channel"));
+        assert!(!filter.keeps("winlog.event_data.LogonType"));
+        assert!(!filter.keeps("message"));
+
+        let mapping = json!({ "logs": { "mappings": { "properties": {
+            "message": { "type": "text" },
+            "n": { "type": "long" },
+            "host": { "properties": {
+                "name": { "type": "keyword" },
+                "ip": { "type": "ip" },
+            }},
+            "winlog": { "properties": {
+                "channel": { "type": "keyword" },
+                "event_data": { "properties": { "LogonType": { "type": "keyword" } } },
+            }},
+        }}}});
+        assert_eq!(
+            filter.project_mapping(&mapping)["_projected"]["mappings"]["properties"],
+            json!({
+                "n": { "type": "long" },
+                "host": { "properties": { "name": { "type": "keyword" } } },
+                "winlog": { "properties": { "channel": { "type": "keyword" } } },
+            })
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::io::Error as IOError;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct Reconciliation {
+    // What _count says the index holds for our query, or what a Source says it holds
+    pub expected: u64,
+    pub expected_from: String,
+    pub written: u64,
+    // Left out by a transform, so they count as accounted for
+    pub dropped: u64,
+    // Only checked when asked for, it means holding every id in memory
+    pub duplicate_ids: Option<u64>,
+}
+
+impl Reconciliation {
+    pub fn is_ok(&self) -> bool {
+        self.expected == self.written + self.dropped && self.duplicate_ids.unwrap_or(0) == 0
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected {} documents from {}, wrote {}",
+            self.expected, self.expected_from, self.written
+        )?;
+        if self.dropped > 0 {
+            write!(f, " and dropped {}", self.dropped)?;
+        }
+        if self.expected != self.written + self.dropped {
+            write!(
+                f,
+                " ({} missing)",
+                self.expected as i64 - (self.written + self.dropped) as i64
+            )?;
+        }
+        if let Some(duplicates) = self.duplicate_ids {
+            write!(f, ", {} duplicate ids", duplicates)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn count_duplicate_ids<P: AsRef<Path>>(paths: &[P]) -> Result<u64, IOError> {
+    // Every other line is an action line carrying the document's index and id, an alias or
+    // pattern can hold the same id in more than one index
+    let mut seen = HashSet::new();
+    let mut duplicates = 0;
+    for path in paths {
+        let reader = BufReader::new(fs::File::open(path)?);
+        for line in reader.lines().step_by(2) {
+            let action: JsonValue = serde_json::from_str(&line?)?;
+            // Documents without an id can't repeat one
+            let meta = &action["index"];
+            let id = meta["_id"].as_str().unwrap_or("");
+            let index = meta["_index"].as_str().unwrap_or("");
+            if !id.is_empty() && !seen.insert((index.to_string(), id.to_string())) {
+                duplicates += 1;
+            }
+        }
+    }
+    Ok(duplicates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_ids_seen_in_more_than_one_slice() {
+        let dir = std::env::temp_dir().join(format!("tana-reconcile-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<_> = (0..2).map(|i| dir.join(format!("{}.json", i))).collect();
+        // Documents read without an id, e.g. from NDJSON, never collide with each other, and
+        // the same id in another index is another document
+        fs::write(
+            &paths[0],
+            "{\"index\":{\"_index\":\"t\",\"_id\":\"a\"}}\n{}\n{\"index\":{\"_index\":\"t\",\"_id\":\"b\"}}\n{}\n{\"index\":{\"_index\":\"t\"}}\n{}\n",
+        )
+        .unwrap();
+        fs::write(
+            &paths[1],
+            "{\"index\":{\"_index\":\"t\",\"_id\":\"a\"}}\n{}\n{\"index\":{\"_index\":\"t\"}}\n{}\n{\"index\":{\"_index\":\"u\",\"_id\":\"b\"}}\n{}\n",
+        )
+        .unwrap();
+
+        let duplicates = count_duplicate_ids(&paths).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(duplicates, 1);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::dumper::error::DumpError;
+use hmac::{Hmac, Mac, NewMac};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const MASK: &str = "REDACTED";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RedactionRule {
+    // e.g. {"field": "user.name", "action": "hash"} or {"pattern": "(^|\\.)ip$", "action": "truncate_ip"}
+    #[serde(flatten)]
+    pub selector: Selector,
+    pub action: Redaction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Selector {
+    // A dotted path into _source, naming an object covers everything in it
+    Field(String),
+    // A regex matched against the dotted path of every field
+    Pattern(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Redaction {
+    Mask,
+    Remove,
+    // Keyed HMAC-SHA256, the same value always gets the same hash so joins still work
+    Hash,
+    // IPv4 addresses to their /24, IPv6 to their /48
+    TruncateIp,
+}
+
+impl fmt::Display for RedactionRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.action {
+            Redaction::Mask => "mask",
+            Redaction::Remove => "remove",
+            Redaction::Hash => "hash",
+            Redaction::TruncateIp => "truncate_ip",
+        };
+        match &self.selector {
+            Selector::Field(field) => write!(f, "{} {}", action, field),
+            Selector::Pattern(pattern) => write!(f, "{} /{}/", action, pattern),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AppliedRedaction {
+    #[serde(flatten)]
+    pub rule: RedactionRule,
+    // How many field values the rule changed or removed
+    pub fields: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Redactor {
+    rules: Vec<RedactionRule>,
+    patterns: Vec<Option<Regex>>,
+    // Never written anywhere, only the rules are recorded
+    key: Option<Vec<u8>>,
+    counts: Vec<AtomicU64>,
+}
+
+impl Redactor {
+    pub fn new(rules: Vec<RedactionRule>, key: Option<Vec<u8>>) -> Result<Redactor, DumpError> {
+        let mut patterns = vec![];
+        for rule in &rules {
+            if rule.action == Redaction::Hash && key.is_none() {
+                return Err(DumpError::Transform(format!(
+                    "'{}' needs an HMAC key",
+                    rule
+                )));
+            }
+            patterns.push(match &rule.selector {
+                Selector::Pattern(pattern) => Some(Regex::new(pattern).map_err(|e| {
+                    DumpError::Transform(format!("'{}' is not a valid regex: {}", pattern, e))
+                })?),
+                Selector::Field(_) => None,
+            });
+        }
+        let counts = rules.iter().map(|_| AtomicU64::new(0)).collect();
+        Ok(Redactor {
+            rules,
+            patterns,
+            key,
+            counts,
+        })
+    }
+
+    pub fn load(path: &Path, key: Option<Vec<u8>>) -> Result<Redactor, DumpError> {
+        // A JSON array of rules, the first that matches a field wins
+        let rules = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Redactor::new(rules, key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn counted(&self) -> Redactor {
+        // The same rules with counts from zero, one per index dumped
+        Redactor {
+            rules: self.rules.clone(),
+            patterns: self.patterns.clone(),
+            key: self.key.clone(),
+            counts: self.rules.iter().map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn applied(&self) -> Vec<AppliedRedaction> {
+        self.rules
+            .iter()
+            .zip(&self.counts)
+            .map(|(rule, count)| AppliedRedaction {
+                rule: rule.clone(),
+                fields: count.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    pub fn redact(&self, source: &mut JsonValue) {
+        if let JsonValue::Object(object) = source {
+            self.walk(object, "");
+        }
+    }
+
+    fn walk(&self, object: &mut Map<String, JsonValue>, prefix: &str) {
+        let keys: Vec<String> = object.keys().cloned().collect();
+        for key in keys {
+            let path = format!("{}{}", prefix, key);
+            match self.rule_for(&path) {
+                Some(at) => {
+                    let changed = match self.rules[at].action {
+                        Redaction::Remove => object.remove(&key).is_some(),
+                        action => self.replace(action, object.get_mut(&key).expect("A key of it")),
+                    };
+                    if changed {
+                        self.counts[at].fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                None => self.descend(object.get_mut(&key).expect("A key of it"), &path),
+            }
+        }
+    }
+
+    fn descend(&self, value: &mut JsonValue, path: &str) {
+        // Objects in an array share the array's path, as in the mapping
+        match value {
+            JsonValue::Object(child) => self.walk(child, &format!("{}.", path)),
+            JsonValue::Array(values) => {
+                for value in values {
+                    self.descend(value, path);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn rule_for(&self, path: &str) -> Option<usize> {
+        self.rules
+            .iter()
+            .zip(&self.patterns)
+            .position(|(rule, pattern)| match (&rule.selector, pattern) {
+                (_, Some(pattern)) => pattern.is_match(path),
+                (Selector::Field(field), None) => field == path,
+                (Selector::Pattern(_), None) => false,
+            })
+    }
+
+    fn replace(&self, action: Redaction, value: &mut JsonValue) -> bool {
+        // Each element of an array on its own, nulls stay null
+        let replaced = match (action, &*value) {
+            (_, JsonValue::Null) => return false,
+            (_, JsonValue::Array(_)) => {
+                let values = value.as_array_mut().expect("Matched above");
+                let mut changed = false;
+                for value in values {
+                    changed |= self.replace(action, value);
+                }
+                return changed;
+            }
+            (Redaction::Mask, _) => JsonValue::from(MASK),
+            (Redaction::Hash, JsonValue::String(text)) => JsonValue::from(self.hash(text)),
+            (Redaction::Hash, value) => JsonValue::from(self.hash(&value.to_string())),
+            (Redaction::TruncateIp, JsonValue::String(text)) => match truncate_ip(text) {
+                Some(truncated) => JsonValue::from(truncated),
+                None => return false,
+            },
+            (Redaction::TruncateIp, _) | (Redaction::Remove, _) => return false,
+        };
+        *value = replaced;
+        true
+    }
+
+    fn hash(&self, text: &str) -> String {
+        let key = self.key.as_deref().expect("Checked in Redactor::new");
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key length");
+        mac.update(text.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+fn truncate_ip(text: &str) -> Option<String> {
+    // Anything that doesn't parse as an address is left as it is
+    match text.parse::<IpAddr>().ok()? {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Some(format!("{}.{}.{}.0", a, b, c))
+        }
+        IpAddr::V6(ip) => {
+            let mut segments = ip.segments();
+            segments[3..].iter_mut().for_each(|segment| *segment = 0);
+            Some(IpAddr::from(segments).to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn redacts_by_field_and_pattern() {
+        let rules = serde_json::from_value(json!([
+            { "field": "user.name", "action": "hash" },
+            { "field": "winlog.event_data", "action": "remove" },
+            { "pattern": "(^|\\.)ip$", "action": "truncate_ip" },
+            { "pattern": "^host\\.", "action": "mask" },
+        ]))
+        .unwrap();
+        let redactor = Redactor::new(rules, Some(b"secret".to_vec())).unwrap();
+
+        let mut source = json!({
+            "user": { "name": "alice" },
+            "source": { "ip": ["10.1.2.3", "2001:db8:1:2::1", "unknown"] },
+            "host": { "name": "WEB-1", "id": null },
+            "winlog": { "channel": "Security", "event_data": { "TargetUserName": "alice" } },
+        });
+        redactor.redact(&mut source);
+        let hash = source["user"]["name"].as_str().unwrap().to_string();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            source,
+            json!({
+                "user": { "name": hash },
+                "source": { "ip": ["10.1.2.0", "2001:db8:1::", "unknown"] },
+                "host": { "name": "REDACTED", "id": null },
+                "winlog": { "channel": "Security" },
+            })
+        );
+
+        // Deterministic, so the same user hashes the same in every document
+        let mut again = json!({ "user": { "name": "alice" } });
+        redactor.redact(&mut again);
+        assert_eq!(again["user"]["name"], json!(hash));
+
+        let fields: Vec<u64> = redactor.applied().iter().map(|a| a.fields).collect();
+        assert_eq!(fields, vec![2, 1, 1, 1]);
+        assert!(Redactor::new(redactor.rules.clone(), None).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::dumper::error::DumpError;
+use crate::dumper::reconcile::Reconciliation;
+use crate::dumper::redact::AppliedRedaction;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SliceStatus {
+    Completed,
+    Failed(String),
+    // Stopped part way through by a CancelToken
+    Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub struct SliceReport {
+    pub slice_id: i64,
+    pub status: SliceStatus,
+    pub documents: u64,
+    pub bytes: u64,
+    // Documents a transform or script left out on purpose
+    pub dropped: u64,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct DumpReport {
+    pub index: String,
+    pub slices: Vec<SliceReport>,
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    pub reconciliation: Option<Reconciliation>,
+    // A count per redaction rule, empty when nothing was redacted
+    pub redactions: Vec<AppliedRedaction>,
+}
+
+impl SliceReport {
+    pub fn new(
+        slice_id: i64,
+        result: Result<(u64, u64, u64), DumpError>,
+        duration: Duration,
+    ) -> SliceReport {
+        // A failed slice reports nothing as written, whatever it got through
+        let (status, documents, bytes, dropped) = match result {
+            Ok((documents, bytes, dropped)) => (SliceStatus::Completed, documents, bytes, dropped),
+            Err(e) => (SliceStatus::Failed(e.to_string()), 0, 0, 0),
+        };
+
+        SliceReport {
+            slice_id,
+            status,
+            documents,
+            bytes,
+            dropped,
+            duration,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == SliceStatus::Completed
+    }
+}
+
+impl DumpReport {
+    pub fn is_complete(&self) -> bool {
+        self.slices.iter().all(|slice| slice.is_ok())
+    }
+
+    pub fn documents(&self) -> u64 {
+        self.slices.iter().map(|slice| slice.documents).sum()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.slices.iter().map(|slice| slice.bytes).sum()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.slices.iter().map(|slice| slice.dropped).sum()
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &SliceReport> {
+        self.slices.iter().filter(|slice| !slice.is_ok())
+    }
+}
+
+impl fmt::Display for DumpReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Index '{}': {} documents, {} bytes in {:.2}s",
+            self.index,
+            self.documents(),
+            self.bytes(),
+            self.duration.as_secs_f64()
+        )?;
+        if self.dropped() > 0 {
+            writeln!(
+                f,
+                "  {} documents dropped by transforms or the script",
+                self.dropped()
+            )?;
+        }
+        for redaction in &self.redactions {
+            writeln!(
+                f,
+                "  redacted: {} in {} fields",
+                redaction.rule, redaction.fields
+            )?;
+        }
+        for slice in &self.slices {
+            let status = match &slice.status {
+                SliceStatus::Completed => "OK".to_string(),
+                SliceStatus::Failed(e) => format!("FAILED: {}", e),
+                SliceStatus::Cancelled => "CANCELLED".to_string(),
+            };
+            writeln!(
+                f,
+                "  slice {}: {} documents, {} bytes in {:.2}s [{}]",
+                slice.slice_id,
+                slice.documents,
+                slice.bytes,
+                slice.duration.as_secs_f64(),
+                status
+            )?;
+        }
+        if let Some(reconciliation) = &self.reconciliation {
+            writeln!(f, "  reconciliation: {}", reconciliation)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_slice_makes_report_incomplete() {
+        let ok = SliceReport::new(0, Ok((10, 100, 0)), Duration::new(1, 0));
+        let failed = SliceReport::new(
+            1,
+            Err(DumpError::S3("boom".to_string())),
+            Duration::new(1, 0),
+        );
+        let report = DumpReport {
+            index: "logs".to_string(),
+            slices: vec![ok, failed],
+            started_at: Utc::now(),
+            duration: Duration::new(2, 0),
+            reconciliation: None,
+            redactions: vec![],
+        };
+
+        assert!(!report.is_complete());
+        assert_eq!(report.documents(), 10);
+        assert_eq!(
+            report.failed().map(|s| s.slice_id).collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+}
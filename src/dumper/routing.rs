@@ -0,0 +1,160 @@
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardRoute {
+    pub slice_id: i64,
+    // Base URL of a node holding these shards
+    pub url: String,
+    pub shards: Vec<u64>,
+}
+
+impl ShardRoute {
+    pub fn preference(&self) -> String {
+        // _local has the node in url serve its own copies rather than forward to another
+        let shards: Vec<String> = self.shards.iter().map(|s| s.to_string()).collect();
+        format!("_shards:{}|_local", shards.join(","))
+    }
+}
+
+impl fmt::Display for ShardRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "slice {} -> {} ({})",
+            self.slice_id,
+            self.url,
+            self.preference()
+        )
+    }
+}
+
+pub fn node_urls(nodes: &JsonValue, scheme: &str) -> HashMap<String, String> {
+    // Node name to base URL, from the response of _nodes/http
+    let mut urls = HashMap::new();
+    if let Some(nodes) = nodes["nodes"].as_object() {
+        for node in nodes.values() {
+            let (name, address) = match (
+                node["name"].as_str(),
+                node["http"]["publish_address"].as_str(),
+            ) {
+                (Some(name), Some(address)) => (name, address),
+                _ => continue,
+            };
+            // Either "host:port" or "hostname/ip:port"
+            let address = address.rsplit('/').next().unwrap_or(address);
+            urls.insert(name.to_string(), format!("{}://{}", scheme, address));
+        }
+    }
+    urls
+}
+
+pub fn plan_routes(
+    shards: &[JsonValue],
+    node_urls: &HashMap<String, String>,
+    slices: i64,
+) -> Result<Vec<ShardRoute>, String> {
+    // _shards:N picks shard N of every index searched, so an alias or pattern over several
+    // indices can't be pinned to one index's shards
+    let mut indices: Vec<&str> = shards.iter().filter_map(|s| s["index"].as_str()).collect();
+    indices.sort_unstable();
+    indices.dedup();
+    if indices.len() > 1 {
+        return Err(format!(
+            "shard routing needs a single index, the target covers {}",
+            indices.join(", ")
+        ));
+    }
+
+    // Which reachable nodes hold a started copy of each shard number
+    let mut holders: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+    for shard in shards {
+        let number = match shard["shard"].as_str().and_then(|n| n.parse().ok()) {
+            Some(number) => number,
+            None => continue,
+        };
+        let urls = holders.entry(number).or_default();
+        if shard["state"].as_str() != Some("STARTED") {
+            continue;
+        }
+        if let Some(url) = shard["node"].as_str().and_then(|node| node_urls.get(node)) {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+    }
+
+    // Skipping a shard would silently leave its documents out of the dump
+    if let Some((number, _)) = holders.iter().find(|(_, urls)| urls.is_empty()) {
+        return Err(format!(
+            "shard {} has no started copy on a reachable node",
+            number
+        ));
+    }
+
+    // Give each shard to its least busy holder so the nodes share the work
+    let mut load: HashMap<String, usize> = HashMap::new();
+    let mut assigned: Vec<(String, u64)> = vec![];
+    for (number, urls) in &holders {
+        let url = urls
+            .iter()
+            .min_by_key(|url| load.get(*url).cloned().unwrap_or(0))
+            .expect("Every shard has a holder")
+            .clone();
+        *load.entry(url.clone()).or_insert(0) += 1;
+        assigned.push((url, *number));
+    }
+    assigned.sort();
+
+    // A slice can't be smaller than a shard, so there are never more slices than shards.
+    // Contiguous runs keep a slice's shards on the same node where possible.
+    let slices = (slices.max(1) as usize).min(assigned.len());
+    Ok((0..slices)
+        .map(|i| {
+            let run = &assigned[i * assigned.len() / slices..(i + 1) * assigned.len() / slices];
+            ShardRoute {
+                slice_id: i as i64,
+                url: run[0].0.clone(),
+                shards: run.iter().map(|(_, number)| *number).collect(),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn spreads_slices_across_shard_holders() {
+        let nodes = json!({ "nodes": {
+            "a": { "name": "node-a", "http": { "publish_address": "es-a/10.0.0.1:9200" } },
+            "b": { "name": "node-b", "http": { "publish_address": "10.0.0.2:9200" } },
+        }});
+        let shards = vec![
+            json!({ "shard": "0", "prirep": "p", "state": "STARTED", "node": "node-a" }),
+            json!({ "shard": "0", "prirep": "r", "state": "STARTED", "node": "node-b" }),
+            json!({ "shard": "1", "prirep": "p", "state": "STARTED", "node": "node-a" }),
+            json!({ "shard": "1", "prirep": "r", "state": "STARTED", "node": "node-b" }),
+            json!({ "shard": "2", "prirep": "p", "state": "INITIALIZING", "node": "node-b" }),
+        ];
+        let urls = node_urls(&nodes, "https");
+        assert!(plan_routes(&shards, &urls, 8).is_err());
+
+        // Shard 0 of two indices isn't one shard
+        let aliased = vec![
+            json!({ "index": "logs-a", "shard": "0", "state": "STARTED", "node": "node-a" }),
+            json!({ "index": "logs-b", "shard": "0", "state": "STARTED", "node": "node-b" }),
+        ];
+        assert!(plan_routes(&aliased, &urls, 2).is_err());
+
+        let shards = &shards[..4];
+        let routes = plan_routes(shards, &urls, 8).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].url, "https://10.0.0.1:9200");
+        assert_eq!(routes[1].url, "https://10.0.0.2:9200");
+        assert_eq!(routes[1].preference(), "_shards:1|_local");
+    }
+}
@@ -0,0 +1,188 @@
+use crate::dumper::error::DumpError;
+use crate::dumper::transform::Document;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    // When the document being run on this thread has had its time
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptLimits {
+    // Wall clock per document, checked as the script runs
+    pub timeout: Duration,
+    pub max_operations: u64,
+    // Caps on what a script can build, so one document can't take the process's memory
+    pub max_string_size: usize,
+    pub max_collection_size: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> ScriptLimits {
+        ScriptLimits {
+            timeout: Duration::from_millis(50),
+            max_operations: 1_000_000,
+            max_string_size: 1 << 20,
+            max_collection_size: 100_000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScriptInfo {
+    // The file it was loaded from and a hash of what it said, the script itself isn't kept
+    pub name: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Keep,
+    Drop,
+    // Written to its own output, {index}/output={name}/
+    Route(String),
+}
+
+pub struct Script {
+    info: ScriptInfo,
+    engine: Engine,
+    ast: AST,
+    timeout: Duration,
+}
+
+impl Script {
+    pub fn load(path: &Path, limits: ScriptLimits) -> Result<Script, DumpError> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Script::new(&name, &fs::read_to_string(path)?, limits)
+    }
+
+    pub fn new(name: &str, source: &str, limits: ScriptLimits) -> Result<Script, DumpError> {
+        // No modules, no eval, and nothing that runs past its limits
+        let mut engine = Engine::new();
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .disable_symbol("eval")
+            .set_max_operations(limits.max_operations)
+            .set_max_string_size(limits.max_string_size)
+            .set_max_array_size(limits.max_collection_size)
+            .set_max_map_size(limits.max_collection_size)
+            .set_max_call_levels(32)
+            .on_progress(|_| {
+                let late =
+                    DEADLINE.with(|deadline| deadline.get().is_some_and(|at| Instant::now() > at));
+                late.then(|| Dynamic::from("timed out"))
+            });
+        let ast = engine
+            .compile(source)
+            .map_err(|e| DumpError::Transform(format!("script '{}': {}", name, e)))?;
+
+        Ok(Script {
+            info: ScriptInfo {
+                name: name.to_string(),
+                sha256: hex::encode(Sha256::digest(source.as_bytes())),
+            },
+            engine,
+            ast,
+            timeout: limits.timeout,
+        })
+    }
+
+    pub fn info(&self) -> &ScriptInfo {
+        &self.info
+    }
+
+    pub fn run(&self, document: &mut Document, hit: &JsonValue) -> Result<Outcome, DumpError> {
+        // The script sees the hit as `hit` and may change its _index, _id and _source.
+        // It returns false to drop it, or a name to route it to that output.
+        let id = document.id.clone();
+        let failed = |e: String| {
+            DumpError::Transform(format!(
+                "script '{}' failed on document '{}': {}",
+                self.info.name, id, e
+            ))
+        };
+        let value = json!({
+            "_index": document.index,
+            "_id": document.id,
+            "_source": document.source,
+            "_routing": hit["_routing"],
+            "_score": hit["_score"],
+            "sort": hit["sort"],
+        });
+        let mut scope = Scope::new();
+        scope.push("hit", to_dynamic(value).map_err(|e| failed(e.to_string()))?);
+
+        DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + self.timeout)));
+        let result = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast);
+        DEADLINE.with(|deadline| deadline.set(None));
+        let result = result.map_err(|e| failed(e.to_string()))?;
+
+        let hit = scope.get_value::<Dynamic>("hit").unwrap_or_default();
+        let hit: JsonValue = from_dynamic(&hit).map_err(|e| failed(e.to_string()))?;
+        if let Some(index) = hit["_index"].as_str() {
+            document.index = index.to_string();
+        }
+        if let Some(id) = hit["_id"].as_str() {
+            document.id = id.to_string();
+        }
+        if hit["_source"].is_object() {
+            document.source = hit["_source"].clone();
+        }
+
+        if result.as_bool() == Ok(false) {
+            Ok(Outcome::Drop)
+        } else if result.is_string() {
+            Ok(Outcome::Route(result.to_string()))
+        } else {
+            Ok(Outcome::Keep)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modifies_drops_and_routes() {
+        let source = r#"
+            if hit._source.level == "debug" { return false; }
+            hit._source.user = hit._source.user.to_upper();
+            hit._index = "archive-" + hit._index;
+            if hit._source.status >= 500 { "errors" }
+        "#;
+        let script = Script::new("test.rhai", source, ScriptLimits::default()).unwrap();
+        let document = |source: JsonValue| Document {
+            index: "logs".to_string(),
+            id: "1".to_string(),
+            source,
+        };
+
+        let mut ok = document(json!({ "user": "alice", "status": 200, "level": "info" }));
+        assert_eq!(script.run(&mut ok, &json!({})).unwrap(), Outcome::Keep);
+        assert_eq!(ok.index, "archive-logs");
+        assert_eq!(ok.source["user"], json!("ALICE"));
+
+        let mut failed = document(json!({ "user": "bob", "status": 503, "level": "info" }));
+        assert_eq!(
+            script.run(&mut failed, &json!({})).unwrap(),
+            Outcome::Route("errors".to_string())
+        );
+        let mut debug = document(json!({ "user": "eve", "status": 200, "level": "debug" }));
+        assert_eq!(script.run(&mut debug, &json!({})).unwrap(), Outcome::Drop);
+
+        let forever = Script::new("loop.rhai", "loop {}", ScriptLimits::default()).unwrap();
+        assert!(forever.run(&mut ok, &json!({})).is_err());
+    }
+}
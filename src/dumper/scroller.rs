@@ -1,218 +1,656 @@
+use crate::dumper::cancel::CancelToken;
+use crate::dumper::error::DumpError;
+use crate::dumper::hit::{Hit, HitPage};
+use crate::dumper::hosts::HostPool;
+use crate::dumper::paging::{AdaptivePageSize, ScrollOptions};
+use crate::dumper::projection::SourceFilter;
+use crate::dumper::redact::Redactor;
+use crate::dumper::report::{SliceReport, SliceStatus};
+use crate::dumper::routing::ShardRoute;
+use crate::dumper::script::{Outcome, Script};
+use crate::dumper::source::Source;
+use crate::dumper::transform::{Document, Pipeline};
 use crate::dumper::Credentials;
-use reqwest::Error as HttpError;
-use reqwest::{Client, ClientBuilder};
+use async_trait::async_trait;
+use futures::stream::{self, Stream, TryStreamExt};
+use reqwest::{Client, Method, StatusCode};
 use serde::Serialize;
+use serde_json::json;
 use serde_json::Value as JsonValue;
 use std::fs;
 use std::io::prelude::*;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
 pub struct Scroller {
     index: String,
     client: Client,
-    url: String,
+    hosts: Arc<HostPool>,
     slice_id: i64,
     max_slices: i64,
+    query: Option<JsonValue>,
+    // _source includes and excludes, so unwanted fields never leave the cluster
+    source: Option<JsonValue>,
+    // Applied to every hit before it's written, it can drop some
+    transforms: Arc<Pipeline>,
+    // Shared by every slice of an index, so its counts are the index's
+    redactor: Arc<Redactor>,
+    // Runs last, on whatever the redactor and transforms left
+    script: Option<Arc<Script>>,
+    options: ScrollOptions,
+    // Set when the slice is pinned to shards, e.g. _shards:0,3
+    preference: Option<String>,
+    // The node holding those shards, tried before the rest of the pool
+    route_url: Option<String>,
+    cancel: CancelToken,
+    // Where a scroller read as a Source has got to
+    creds: Credentials,
+    scroll_id: Option<String>,
+    exhausted: bool,
 }
 #[derive(Serialize, Debug)]
 struct ActionMeta {
     index: Index,
+    // Only in slice files, taken off again before the document is written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
 struct Index {
     _index: String,
+    // Left out for documents read without one, they get a new id when restored
+    #[serde(skip_serializing_if = "String::is_empty")]
     _id: String,
+    // Restored to the same shard, indices that require routing refuse documents without it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    routing: Option<String>,
+}
+
+// One page of hits, already in bulk format
+struct Page {
+    hits_json: String,
+    documents: u64,
+    // Hits a transform dropped, the page isn't the last just because it wrote nothing
+    dropped: u64,
+    total: i64,
+    scroll_id: String,
+    pit_id: Option<String>,
+    last_sort: Option<JsonValue>,
 }
 
 impl Scroller {
     pub fn new(
-        url: String,
+        client: Client,
+        hosts: Arc<HostPool>,
         index: String,
         slice_id: i64,
         max_slices: i64,
-    ) -> Result<Scroller, HttpError> {
-        // Create new client with API base URL - default 10s timeout
-        let ten_seconds = Duration::new(10, 0);
-        let client = ClientBuilder::new()
-            .timeout(ten_seconds)
-            .danger_accept_invalid_certs(true)
-            .build()?;
-
-        Ok(Scroller {
-            index: index,
-            slice_id: slice_id,
-            max_slices: max_slices,
-            client: client,
-            url: url,
-        })
+        options: ScrollOptions,
+        cancel: CancelToken,
+    ) -> Scroller {
+        // The Dumper's client, so its timeouts and TLS settings apply to every slice
+        Scroller {
+            index,
+            slice_id,
+            max_slices,
+            query: None,
+            source: None,
+            transforms: Arc::new(Pipeline::default()),
+            redactor: Arc::new(Redactor::default()),
+            script: None,
+            options,
+            preference: None,
+            route_url: None,
+            cancel,
+            client,
+            hosts,
+            creds: Credentials::default(),
+            scroll_id: None,
+            exhausted: false,
+        }
     }
 
-    pub async fn scroll(self, creds: Credentials) -> Result<i64, HttpError> {
-        // We only want to consume 1000 documents at a time
-        let url = format!("{}/{}/_search?scroll=1m&size=1000", &self.url, &self.index);
-        let payload = format!(
-            "{{
-        \"slice\": {{
-                \"id\": {}, 
-                \"max\": {} 
-        }},
-        \"sort\": [\"_doc\"]
-        }}",
-            self.slice_id, self.max_slices
-        );
+    pub fn with_route(mut self, route: &ShardRoute) -> Scroller {
+        // Read only these shards, straight from a node holding them
+        self.route_url = Some(route.url.clone());
+        self.preference = Some(route.preference());
+        self
+    }
 
-        let json_str = self
-            .client
-            .get(&url)
-            .body(payload)
-            .header("Content-Type", "application/json")
-            .basic_auth(&creds.username, Some(&creds.password))
-            .send()
-            .await?
-            .text()
-            .await?;
+    pub fn with_query(mut self, query: Option<JsonValue>) -> Scroller {
+        self.query = query;
+        self
+    }
 
-        // Parse the JSON response and keep it here in memory
-        let (mut total_hits_json, hit_count, scroll_id) = Scroller::parse_json(json_str);
+    pub fn with_source_filter(mut self, filter: &SourceFilter) -> Scroller {
+        if !filter.is_empty() {
+            self.source = Some(filter.to_json());
+        }
+        self
+    }
 
-        // Scroll to end
-        total_hits_json = format!(
-            "{}{}",
-            total_hits_json,
-            self.scroll_to_end(&creds, scroll_id.clone()).await?
-        );
+    pub fn with_transforms(mut self, transforms: Arc<Pipeline>) -> Scroller {
+        self.transforms = transforms;
+        self
+    }
 
-        // Dump to disk
-        println!(
-            "Writing {:?} documents.",
-            total_hits_json.matches("\n").count() / 2
-        );
-        Scroller::write_to_disk(
-            format!("{}-{}.json", &self.index, &self.slice_id),
-            total_hits_json,
-        );
+    pub fn with_redactor(mut self, redactor: Arc<Redactor>) -> Scroller {
+        self.redactor = redactor;
+        self
+    }
 
-        // Clear the scroll_id so Elasticsearch doesn't run out of file handles!
-        self.clear(creds, scroll_id).await?;
+    pub fn with_script(mut self, script: Option<Arc<Script>>) -> Scroller {
+        self.script = script;
+        self
+    }
+
+    pub fn with_credentials(mut self, creds: Credentials) -> Scroller {
+        // Only needed to read it as a Source, scroll and hits take their own
+        self.creds = creds;
+        self
+    }
+
+    fn search_params(&self) -> String {
+        match &self.preference {
+            Some(preference) => format!("&preference={}", preference.replace('|', "%7C")),
+            None => "".to_string(),
+        }
+    }
+
+    pub async fn scroll(self, creds: Credentials, path: PathBuf) -> SliceReport {
+        // Never lose a slice's failure: it always ends up in the report
+        let started = Instant::now();
+        let slice_id = self.slice_id;
+        let cancel = self.cancel.clone();
+        let result = self.scroll_slice(creds, &path).await;
+        if let Err(e) = &result {
+            println!("Slice {} failed: {}", slice_id, e);
+        }
+        let mut report = SliceReport::new(slice_id, result, started.elapsed());
+        if report.is_ok() && cancel.is_cancelled() {
+            report.status = SliceStatus::Cancelled;
+        }
+        report
+    }
+
+    async fn scroll_slice(
+        self,
+        creds: Credentials,
+        path: &Path,
+    ) -> Result<(u64, u64, u64), DumpError> {
+        // Each page goes straight to disk, only one is ever held in memory
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        let written = if self.options.adaptive {
+            self.page_to_end(&creds, &mut file).await?
+        } else {
+            self.scroll_to_end(&creds, &mut file).await?
+        };
+        file.flush()?;
 
-        Ok(hit_count)
+        println!("Wrote {} documents for slice {}.", written.0, self.slice_id);
+        Ok(written)
+    }
+
+    pub fn hits(self, creds: Credentials) -> impl Stream<Item = Result<Hit, DumpError>> + Send {
+        // One page at a time as the caller asks for them, the scroll is cleared after the last.
+        // A stream dropped part way leaves its scroll to expire after keep_alive.
+        let scroller = self.with_credentials(creds);
+        stream::try_unfold(scroller, |mut scroller| async move {
+            let hits = scroller.next_page().await?;
+            if hits.is_empty() {
+                return Ok::<_, DumpError>(None);
+            }
+            Ok(Some((stream::iter(hits.into_iter().map(Ok)), scroller)))
+        })
+        .try_flatten()
+    }
+
+    fn search_body(&self) -> JsonValue {
+        let mut payload = json!({});
+        // Elasticsearch refuses a slice max of 1, a single slice is just a scroll
+        if self.max_slices > 1 {
+            payload["slice"] = json!({ "id": self.slice_id, "max": self.max_slices });
+        }
+        if let Some(query) = &self.query {
+            payload["query"] = query.clone();
+        }
+        if let Some(source) = &self.source {
+            payload["_source"] = source.clone();
+        }
+        payload
     }
 
     async fn scroll_to_end(
         &self,
         creds: &Credentials,
-        mut scroll_id: String,
-    ) -> Result<String, HttpError> {
-        // Make the request for this crumb
-        let url = format!("{}/_search/scroll", &self.url);
-        let mut hit_count: i64 = -1;
-        let mut total_hits_json = "".to_string();
-        let mut hits_json: String;
-        while hit_count != 0 {
-            let payload = format!(
-                "{{
-                  \"scroll\": \"1m\",
-                  \"scroll_id\": {}
-            }}",
-                scroll_id
+        file: &mut fs::File,
+    ) -> Result<(u64, u64, u64), DumpError> {
+        // The page size is fixed for the life of a scroll
+        let path = format!(
+            "/{}/_search?scroll={}&size={}{}",
+            &self.index,
+            &self.options.keep_alive,
+            self.options.page_size,
+            self.search_params()
+        );
+        let mut payload = self.search_body();
+        payload["sort"] = json!(["_doc"]);
+        // Cancelled before the first page, there's no scroll id to clear yet
+        let page = match self.send(Method::GET, &path, payload, creds).await? {
+            Some(page) => page,
+            None => return Ok((0, 0, 0)),
+        };
+
+        // Clear the scroll_id so Elasticsearch doesn't run out of file handles!
+        // Also when a page fails part way, the error is what gets reported.
+        let mut scroll_id = page.scroll_id.clone();
+        let written = self.scroll_pages(creds, file, page, &mut scroll_id).await;
+        let cleared = self.clear(creds, &scroll_id).await;
+        if let (Err(_), Err(e)) = (&written, &cleared) {
+            println!(
+                "Unable to clear the scroll of slice {}: {}",
+                self.slice_id, e
+            );
+        }
+        let written = written?;
+        cleared?;
+        Ok(written)
+    }
+
+    async fn scroll_pages(
+        &self,
+        creds: &Credentials,
+        file: &mut fs::File,
+        mut page: Page,
+        scroll_id: &mut String,
+    ) -> Result<(u64, u64, u64), DumpError> {
+        // Make the request for each crumb until we get an empty one
+        let (mut documents, mut bytes, mut dropped) = (0, 0, 0);
+        while page.documents + page.dropped != 0 {
+            file.write_all(page.hits_json.as_bytes())?;
+            documents += page.documents;
+            dropped += page.dropped;
+            bytes += page.hits_json.len() as u64;
+            println!(
+                "Writing {} entries [{} of {}]",
+                page.documents, documents, page.total
             );
 
-            let json_str = self
-                .client
-                .post(&url)
-                .body(payload)
-                .header("Content-Type", "application/json")
-                .basic_auth(&creds.username, Some(&creds.password))
-                .send()
+            if self.cancel.is_cancelled() {
+                println!("Slice {} cancelled, clearing its scroll.", self.slice_id);
+                break;
+            }
+            let payload = json!({
+                "scroll": &self.options.keep_alive,
+                "scroll_id": &page.scroll_id,
+            });
+            page = match self
+                .send(Method::POST, "/_search/scroll", payload, creds)
                 .await?
-                .text()
-                .await?;
-
-            // Parse the JSON and keep it in memory
-            let parse_tuple = Scroller::parse_json(json_str);
-            hits_json = parse_tuple.0;
-            hit_count = parse_tuple.1;
-            scroll_id = parse_tuple.2;
-            if hits_json != "" {
-                println!("Writing 1000 entries [TOTAL {}]", hit_count);
-                total_hits_json = format!("{}{}", total_hits_json, hits_json);
-            } else {
-                hit_count = 0
+            {
+                Some(page) => page,
+                None => {
+                    println!("Slice {} cancelled, clearing its scroll.", self.slice_id);
+                    break;
+                }
+            };
+            *scroll_id = page.scroll_id.clone();
+        }
+        Ok((documents, bytes, dropped))
+    }
+
+    async fn page_to_end(
+        &self,
+        creds: &Credentials,
+        file: &mut fs::File,
+    ) -> Result<(u64, u64, u64), DumpError> {
+        // A point in time lets every page ask for a different size
+        let path = format!(
+            "/{}/_pit?keep_alive={}{}",
+            &self.index,
+            &self.options.keep_alive,
+            self.search_params()
+        );
+        let v: JsonValue = serde_json::from_str(
+            &self
+                .hosts
+                .send(
+                    &self.client,
+                    Method::POST,
+                    &path,
+                    None,
+                    creds,
+                    self.pinned(),
+                )
+                .await?,
+        )?;
+        let mut pit_id = v["id"].as_str().unwrap_or("").to_string();
+
+        // Close the point in time, it holds segments open like a scroll does.
+        // Also when a page fails part way, the error is what gets reported.
+        let written = self.search_pages(creds, file, &mut pit_id).await;
+        let payload = json!({ "id": pit_id });
+        let closed = self
+            .hosts
+            .send(
+                &self.client,
+                Method::DELETE,
+                "/_pit",
+                Some(payload.to_string()),
+                creds,
+                self.pinned(),
+            )
+            .await;
+        if let (Err(_), Err(e)) = (&written, &closed) {
+            println!(
+                "Unable to close the point in time of slice {}: {}",
+                self.slice_id, e
+            );
+        }
+        let written = written?;
+        closed?;
+        Ok(written)
+    }
+
+    async fn search_pages(
+        &self,
+        creds: &Credentials,
+        file: &mut fs::File,
+        pit_id: &mut String,
+    ) -> Result<(u64, u64, u64), DumpError> {
+        let path = format!("/_search?{}", self.search_params().trim_start_matches('&'));
+        let mut page_size = AdaptivePageSize::new(self.options.page_size);
+        let mut search_after: Option<JsonValue> = None;
+        let (mut documents, mut bytes, mut dropped) = (0, 0, 0);
+        while !self.cancel.is_cancelled() {
+            let mut payload = self.search_body();
+            payload["size"] = json!(page_size.current());
+            payload["sort"] = json!([{ "_shard_doc": "asc" }]);
+            payload["pit"] = json!({ "id": &*pit_id, "keep_alive": &self.options.keep_alive });
+            if let Some(after) = &search_after {
+                payload["search_after"] = after.clone();
+            }
+
+            let started = Instant::now();
+            let page = match self.send(Method::POST, &path, payload, creds).await {
+                Ok(Some(page)) => page,
+                Ok(None) => break,
+                Err(e) if Scroller::is_backpressure(&e) => {
+                    // The same search_after again is safe, nothing was consumed
+                    if !page_size.failed() {
+                        return Err(e);
+                    }
+                    println!(
+                        "Slice {} backing off to {} hits per page: {}",
+                        self.slice_id,
+                        page_size.current(),
+                        e
+                    );
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            page_size.succeeded(started.elapsed());
+
+            if let Some(id) = page.pit_id {
+                *pit_id = id;
+            }
+            if page.documents + page.dropped == 0 {
+                break;
+            }
+            file.write_all(page.hits_json.as_bytes())?;
+            documents += page.documents;
+            dropped += page.dropped;
+            bytes += page.hits_json.len() as u64;
+            search_after = page.last_sort;
+            println!(
+                "Writing {} entries [{} of {}]",
+                page.documents, documents, page.total
+            );
+        }
+
+        if self.cancel.is_cancelled() {
+            println!(
+                "Slice {} cancelled, closing its point in time.",
+                self.slice_id
+            );
+        }
+        Ok((documents, bytes, dropped))
+    }
+
+    fn pinned(&self) -> Option<&str> {
+        self.route_url.as_deref()
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        payload: JsonValue,
+        creds: &Credentials,
+    ) -> Result<Option<Page>, DumpError> {
+        match self.fetch(method, path, payload, creds).await? {
+            Some(json_str) => self.parse_json(json_str).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn fetch(
+        &self,
+        method: Method,
+        path: &str,
+        payload: JsonValue,
+        creds: &Credentials,
+    ) -> Result<Option<String>, DumpError> {
+        // None when cancelled mid-request, a slow page shouldn't hold up a cancel
+        let request = self.hosts.send(
+            &self.client,
+            method,
+            path,
+            Some(payload.to_string()),
+            creds,
+            self.pinned(),
+        );
+        tokio::select! {
+            json_str = request => json_str.map(Some),
+            _ = self.cancel.cancelled() => Ok(None),
+        }
+    }
+
+    fn is_backpressure(e: &DumpError) -> bool {
+        // Timeouts and tripped circuit breakers (429) mean the page was too big
+        match e {
+            DumpError::Http(e) => {
+                e.is_timeout() || e.status() == Some(StatusCode::TOO_MANY_REQUESTS)
             }
+            _ => false,
         }
-        Ok(total_hits_json)
     }
 
-    fn parse_json(json_str: String) -> (String, i64, String) {
-        let v: JsonValue = serde_json::from_str(&json_str).expect("Unable to parse JSON.");
+    fn parse_json(&self, json_str: String) -> Result<Page, DumpError> {
+        let v: JsonValue = serde_json::from_str(&json_str)?;
 
         // We safely presume hits is an Array.
         let hits = &v["hits"]["hits"];
         let mut hits_json = "".to_string();
-        let mut hits_vec: &Vec<JsonValue> = &vec![];
-        hits_vec = hits.as_array().unwrap_or(hits_vec);
-
-        let offset = &v["hits"]["hits"][0]["sort"][0];
-        let hit_count = v["hits"]["total"]["value"].as_i64().unwrap_or(0);
+        let empty = vec![];
+        let hits_vec: &Vec<JsonValue> = hits.as_array().unwrap_or(&empty);
+        let (mut documents, mut dropped) = (0, 0);
 
         for hit in hits_vec.iter() {
-            let index = Index {
-                _index: hit["_index"].as_str().unwrap_or("").to_string(),
-                _id: hit["_id"].as_str().unwrap_or("").to_string(),
-            };
-            let action_meta = ActionMeta { index: index };
-            let source = &hit["_source"];
-            let action_meta_parsed = serde_json::to_string(&action_meta);
-            let source_parsed = serde_json::to_string(&source);
-            if action_meta_parsed.is_ok() && source_parsed.is_ok() {
-                // Append action meta for the new document
-                hits_json = format!("{}{}", hits_json, action_meta_parsed.unwrap());
-                // Append the index data for the new document
-                hits_json = format!("{}\n{}\n", hits_json, source_parsed.unwrap());
+            let script = self.script.as_deref();
+            if write_bulk(
+                hit,
+                &self.redactor,
+                &self.transforms,
+                script,
+                &mut hits_json,
+            )? {
+                documents += 1;
             } else {
-                println!(
-                    "Failed to parse action meta or source: {:?} {:?}",
-                    action_meta_parsed.err(),
-                    source_parsed.err()
-                );
+                dropped += 1;
             }
         }
-        (hits_json, hit_count, v["_scroll_id"].to_string())
-    }
 
-    fn write_to_disk(filename: String, hits_json: String) {
-        // Get the values we need from the JSON response
-        let mut file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&filename)
-            .expect("Unable to open consolidation file.");
-
-        file.write(hits_json.as_bytes())
-            .expect("Unable to write file");
+        Ok(Page {
+            hits_json,
+            documents,
+            dropped,
+            total: v["hits"]["total"]["value"].as_i64().unwrap_or(0),
+            scroll_id: v["_scroll_id"].as_str().unwrap_or("").to_string(),
+            pit_id: v["pit_id"].as_str().map(|id| id.to_string()),
+            last_sort: hits_vec.last().map(|hit| hit["sort"].clone()),
+        })
     }
 
-    async fn clear(self, creds: Credentials, scroll_id: String) -> Result<(), HttpError> {
-        let url = format!("{}/_search/scroll", &self.url);
-        let payload = format!(
-            "{{
-        \"scroll_id\": {}
-        }}",
-            scroll_id
-        );
+    async fn clear(&self, creds: &Credentials, scroll_id: &str) -> Result<(), DumpError> {
+        let payload = json!({ "scroll_id": scroll_id });
 
-        let _json_str = self
-            .client
-            .delete(&url)
-            .body(payload)
-            .header("Content-Type", "application/json")
-            .basic_auth(&creds.username, Some(&creds.password))
-            .send()
-            .await?
-            .text()
+        self.hosts
+            .send(
+                &self.client,
+                Method::DELETE,
+                "/_search/scroll",
+                Some(payload.to_string()),
+                creds,
+                self.pinned(),
+            )
             .await?;
 
         Ok(())
     }
 }
+
+#[async_trait]
+impl Source for Scroller {
+    async fn next_page(&mut self) -> Result<Vec<Hit>, DumpError> {
+        // The first page opens the scroll, the empty one after the last clears it
+        if self.exhausted {
+            return Ok(vec![]);
+        }
+        let text = match &self.scroll_id {
+            None => {
+                let path = format!(
+                    "/{}/_search?scroll={}&size={}{}",
+                    &self.index,
+                    &self.options.keep_alive,
+                    self.options.page_size,
+                    self.search_params()
+                );
+                let mut payload = self.search_body();
+                payload["sort"] = json!(["_doc"]);
+                self.fetch(Method::GET, &path, payload, &self.creds).await?
+            }
+            Some(scroll_id) => {
+                let payload = json!({
+                    "scroll": &self.options.keep_alive,
+                    "scroll_id": scroll_id,
+                });
+                self.fetch(Method::POST, "/_search/scroll", payload, &self.creds)
+                    .await?
+            }
+        };
+        let text = match text {
+            Some(text) => text,
+            None => {
+                self.exhausted = true;
+                if let Some(scroll_id) = &self.scroll_id {
+                    self.clear(&self.creds, scroll_id).await?;
+                }
+                return Ok(vec![]);
+            }
+        };
+        let page: HitPage = serde_json::from_str(&text)?;
+        if page.hits.hits.is_empty() || self.cancel.is_cancelled() {
+            self.exhausted = true;
+            self.clear(&self.creds, &page.scroll_id).await?;
+            return Ok(vec![]);
+        }
+        self.scroll_id = Some(page.scroll_id);
+        Ok(page.hits.hits)
+    }
+}
+
+pub(crate) fn write_bulk(
+    hit: &JsonValue,
+    redactor: &Redactor,
+    transforms: &Pipeline,
+    script: Option<&Script>,
+    out: &mut String,
+) -> Result<bool, DumpError> {
+    // A hit as an action and source line, false if a transform or the script dropped it
+    let mut document = Document {
+        index: hit["_index"].as_str().unwrap_or("").to_string(),
+        id: hit["_id"].as_str().unwrap_or("").to_string(),
+        // A hit without a _source still goes out, so its id is in the dump
+        source: match &hit["_source"] {
+            JsonValue::Null => json!({}),
+            source => source.clone(),
+        },
+    };
+    // Redacted first, so nothing a transform copies carries the original value
+    if !redactor.is_empty() {
+        redactor.redact(&mut document.source);
+    }
+    if !transforms.apply(&mut document)? {
+        return Ok(false);
+    }
+    let output = match script {
+        Some(script) => match script.run(&mut document, hit)? {
+            Outcome::Keep => None,
+            Outcome::Route(output) => Some(output),
+            Outcome::Drop => return Ok(false),
+        },
+        None => None,
+    };
+    let index = Index {
+        _index: document.index,
+        _id: document.id,
+        routing: hit["_routing"].as_str().map(|routing| routing.to_string()),
+    };
+    let action_meta = ActionMeta { index, output };
+    out.push_str(&serde_json::to_string(&action_meta)?);
+    out.push('\n');
+    out.push_str(&serde_json::to_string(&document.source)?);
+    out.push('\n');
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_routing_and_hits_without_a_source() {
+        let hits = [
+            json!({"_index": "logs", "_id": "1", "_routing": "eu", "_source": {"n": 1}}),
+            json!({"_index": "logs", "_id": "2", "_source": {"n": 2}}),
+            json!({"_index": "logs", "_id": "3"}),
+        ];
+        let mut out = String::new();
+        for hit in &hits {
+            write_bulk(
+                hit,
+                &Redactor::default(),
+                &Pipeline::default(),
+                None,
+                &mut out,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            out,
+            "{\"index\":{\"_index\":\"logs\",\"_id\":\"1\",\"routing\":\"eu\"}}\n{\"n\":1}\n\
+             {\"index\":{\"_index\":\"logs\",\"_id\":\"2\"}}\n{\"n\":2}\n\
+             {\"index\":{\"_index\":\"logs\",\"_id\":\"3\"}}\n{}\n"
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use crate::dumper::error::DumpError;
+use async_trait::async_trait;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use s3::bucket::Bucket as S3Bucket;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::mem;
+use std::path::{Path, PathBuf};
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stored {
+    // What an object ended up as, for its entry in the manifest
+    pub name: String,
+    pub compression: Option<String>,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[async_trait]
+pub trait Sink: Send {
+    // Start an object, named relative to the manifest, e.g. logs/date=2020-04-20/part-00000.json.
+    // compression is what the object already has, e.g. snappy for a Parquet part.
+    async fn create(&mut self, name: &str, compression: Option<&str>) -> Result<(), DumpError>;
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), DumpError>;
+    // End the current object, it's complete once this returns
+    async fn flush(&mut self) -> Result<Stored, DumpError>;
+    // Store the manifest as it is, once every object it lists is complete
+    async fn finish(&mut self, name: &str, manifest: &[u8]) -> Result<(), DumpError>;
+    // True if each object is held in memory until it's flushed, so dumps must bound their parts
+    fn holds_objects(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    async fn create(&mut self, name: &str, compression: Option<&str>) -> Result<(), DumpError> {
+        (**self).create(name, compression).await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), DumpError> {
+        (**self).write(bytes).await
+    }
+
+    async fn flush(&mut self) -> Result<Stored, DumpError> {
+        (**self).flush().await
+    }
+
+    async fn finish(&mut self, name: &str, manifest: &[u8]) -> Result<(), DumpError> {
+        (**self).finish(name, manifest).await
+    }
+
+    fn holds_objects(&self) -> bool {
+        (**self).holds_objects()
+    }
+}
+
+// Counts and hashes an object's bytes as they go out
+struct Checksum {
+    name: String,
+    compression: Option<String>,
+    bytes: u64,
+    hasher: Sha256,
+}
+
+impl Checksum {
+    fn new(name: &str, compression: Option<&str>) -> Checksum {
+        Checksum {
+            name: name.to_string(),
+            compression: compression.map(|c| c.to_string()),
+            bytes: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.bytes += bytes.len() as u64;
+        self.hasher.update(bytes);
+    }
+
+    fn stored(self) -> Stored {
+        Stored {
+            name: self.name,
+            compression: self.compression,
+            bytes: self.bytes,
+            sha256: hex::encode(self.hasher.finalize()),
+        }
+    }
+}
+
+fn no_object() -> DumpError {
+    DumpError::Config("Nothing was created to write to".to_string())
+}
+
+pub struct DiskSink {
+    dir: PathBuf,
+    file: Option<(BufWriter<fs::File>, Checksum)>,
+}
+
+impl DiskSink {
+    pub fn new(dir: &Path) -> DiskSink {
+        DiskSink {
+            dir: dir.to_path_buf(),
+            file: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for DiskSink {
+    async fn create(&mut self, name: &str, compression: Option<&str>) -> Result<(), DumpError> {
+        let path = self.dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = BufWriter::new(fs::File::create(&path)?);
+        self.file = Some((file, Checksum::new(name, compression)));
+        Ok(())
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), DumpError> {
+        let (file, checksum) = self.file.as_mut().ok_or_else(no_object)?;
+        file.write_all(bytes)?;
+        checksum.update(bytes);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<Stored, DumpError> {
+        let (mut file, checksum) = self.file.take().ok_or_else(no_object)?;
+        file.flush()?;
+        println!("Wrote {}", self.dir.join(&checksum.name).display());
+        Ok(checksum.stored())
+    }
+
+    async fn finish(&mut self, name: &str, manifest: &[u8]) -> Result<(), DumpError> {
+        let path = self.dir.join(name);
+        fs::write(&path, manifest)?;
+        println!("Wrote manifest {}", path.display());
+        Ok(())
+    }
+}
+
+pub struct S3Sink {
+    bucket: S3Bucket,
+    // Objects go up whole in a single PUT, so each is held until it's flushed
+    object: Option<(Vec<u8>, Checksum)>,
+}
+
+impl S3Sink {
+    pub fn new(bucket: S3Bucket) -> S3Sink {
+        S3Sink {
+            bucket,
+            object: None,
+        }
+    }
+
+    fn put(&self, key: &str, content: &[u8]) -> Result<(), DumpError> {
+        println!("PUT {} to S3.", key);
+        let (_, code) = self
+            .bucket
+            .put_object(key, content, "data/binary")
+            .map_err(|e| DumpError::S3(e.to_string()))?;
+        if code != 200 {
+            return Err(DumpError::S3(format!(
+                "Error putting {} to S3: {}",
+                key, code
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for S3Sink {
+    async fn create(&mut self, name: &str, compression: Option<&str>) -> Result<(), DumpError> {
+        self.object = Some((vec![], Checksum::new(name, compression)));
+        Ok(())
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), DumpError> {
+        let (content, checksum) = self.object.as_mut().ok_or_else(no_object)?;
+        content.extend_from_slice(bytes);
+        checksum.update(bytes);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<Stored, DumpError> {
+        let (content, checksum) = self.object.take().ok_or_else(no_object)?;
+        self.put(&checksum.name, &content)?;
+        Ok(checksum.stored())
+    }
+
+    async fn finish(&mut self, name: &str, manifest: &[u8]) -> Result<(), DumpError> {
+        self.put(name, manifest)
+    }
+
+    fn holds_objects(&self) -> bool {
+        true
+    }
+}
+
+pub struct GzipSink<S: Sink> {
+    inner: S,
+    // None for an object that's passed through as it is
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl<S: Sink> GzipSink<S> {
+    pub fn new(inner: S) -> GzipSink<S> {
+        GzipSink {
+            inner,
+            encoder: None,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Sink> Sink for GzipSink<S> {
+    async fn create(&mut self, name: &str, compression: Option<&str>) -> Result<(), DumpError> {
+        // Already compressed objects, such as Parquet and Avro, aren't worth compressing again
+        if compression.is_some() {
+            self.encoder = None;
+            return self.inner.create(name, compression).await;
+        }
+        self.encoder = Some(GzEncoder::new(vec![], Compression::best()));
+        let name = Path::new(name).with_extension("gz");
+        self.inner
+            .create(&name.to_string_lossy(), Some("gzip"))
+            .await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), DumpError> {
+        let compressed = match self.encoder.as_mut() {
+            Some(encoder) => {
+                encoder.write_all(bytes)?;
+                mem::take(encoder.get_mut())
+            }
+            None => return self.inner.write(bytes).await,
+        };
+        self.inner.write(&compressed).await
+    }
+
+    async fn flush(&mut self) -> Result<Stored, DumpError> {
+        if let Some(encoder) = self.encoder.take() {
+            let tail = encoder.finish()?;
+            self.inner.write(&tail).await?;
+        }
+        self.inner.flush().await
+    }
+
+    async fn finish(&mut self, name: &str, manifest: &[u8]) -> Result<(), DumpError> {
+        // Manifests are never compressed, they're read before anything else
+        self.inner.finish(name, manifest).await
+    }
+
+    fn holds_objects(&self) -> bool {
+        self.inner.holds_objects()
+    }
+}
+
+pub struct ZstdSink<S: Sink> {
+    inner: S,
+    level: i32,
+    // None for an object that's passed through as it is
+    encoder: Option<ZstdEncoder<'static, Vec<u8>>>,
+}
+
+impl<S: Sink> ZstdSink<S> {
+    pub fn new(inner: S, level: i32) -> ZstdSink<S> {
+        ZstdSink {
+            inner,
+            level,
+            encoder: None,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Sink> Sink for ZstdSink<S> {
+    async fn create(&mut self, name: &str, compression: Option<&str>) -> Result<(), DumpError> {
+        if compression.is_some() {
+            self.encoder = None;
+            return self.inner.create(name, compression).await;
+        }
+        self.encoder = Some(ZstdEncoder::new(vec![], self.level)?);
+        let name = Path::new(name).with_extension("zst");
+        self.inner
+            .create(&name.to_string_lossy(), Some("zstd"))
+            .await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), DumpError> {
+        let compressed = match self.encoder.as_mut() {
+            Some(encoder) => {
+                encoder.write_all(bytes)?;
+                mem::take(encoder.get_mut())
+            }
+            None => return self.inner.write(bytes).await,
+        };
+        self.inner.write(&compressed).await
+    }
+
+    async fn flush(&mut self) -> Result<Stored, DumpError> {
+        if let Some(encoder) = self.encoder.take() {
+            let tail = encoder.finish()?;
+            self.inner.write(&tail).await?;
+        }
+        self.inner.flush().await
+    }
+
+    async fn finish(&mut self, name: &str, manifest: &[u8]) -> Result<(), DumpError> {
+        self.inner.finish(name, manifest).await
+    }
+
+    fn holds_objects(&self) -> bool {
+        self.inner.holds_objects()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    #[tokio::test]
+    async fn layers_compression_over_disk() {
+        let dir = std::env::temp_dir().join(format!("tana-sink-test-{}", std::process::id()));
+        let mut sink: Box<dyn Sink> = Box::new(GzipSink::new(DiskSink::new(&dir)));
+
+        sink.create("logs/part-00000.json", None).await.unwrap();
+        sink.write(b"{\"index\":{}}\n").await.unwrap();
+        sink.write(b"{}\n").await.unwrap();
+        let stored = sink.flush().await.unwrap();
+        assert_eq!(stored.name, "logs/part-00000.gz");
+        assert_eq!(stored.compression.as_deref(), Some("gzip"));
+
+        let content = fs::read(dir.join(&stored.name)).unwrap();
+        assert_eq!(stored.bytes, content.len() as u64);
+        assert_eq!(stored.sha256, hex::encode(Sha256::digest(&content)));
+        let mut text = String::new();
+        GzDecoder::new(&content[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "{\"index\":{}}\n{}\n");
+
+        // Parquet pages are compressed already, so they go through untouched
+        sink.create("logs.parquet", Some("snappy")).await.unwrap();
+        sink.write(b"PAR1").await.unwrap();
+        let stored = sink.flush().await.unwrap();
+        assert_eq!(stored.name, "logs.parquet");
+        assert_eq!(stored.compression.as_deref(), Some("snappy"));
+        sink.finish("logs.manifest.json", b"{}").await.unwrap();
+        assert!(dir.join("logs.manifest.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,438 @@
+use crate::dumper::error::DumpError;
+use crate::dumper::format::OutputFormat;
+use crate::dumper::hit::Hit;
+use crate::dumper::manifest::{ClusterInfo, Manifest};
+use crate::dumper::verify;
+use async_trait::async_trait;
+use flate2::read::MultiGzDecoder;
+use s3::bucket::Bucket as S3Bucket;
+use serde_json::value::RawValue;
+use serde_json::{Map, Value as JsonValue};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::prelude::*;
+use std::io::{BufReader, Error as IOError};
+use std::path::{Path, PathBuf};
+
+// Documents handed over per page, a source never holds more than this in memory
+const PAGE_SIZE: usize = 1000;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[async_trait]
+pub trait Source: Send {
+    // The next page of hits, empty once there are no more
+    async fn next_page(&mut self) -> Result<Vec<Hit>, DumpError>;
+
+    // The cluster the documents first came from, recorded in the new manifest
+    fn cluster(&self) -> ClusterInfo {
+        ClusterInfo::default()
+    }
+
+    // How many documents there should be, when the source knows, to reconcile against
+    fn expected(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[async_trait]
+impl<S: Source + ?Sized> Source for Box<S> {
+    async fn next_page(&mut self) -> Result<Vec<Hit>, DumpError> {
+        (**self).next_page().await
+    }
+
+    fn cluster(&self) -> ClusterInfo {
+        (**self).cluster()
+    }
+
+    fn expected(&self) -> Option<u64> {
+        (**self).expected()
+    }
+}
+
+pub(crate) fn open_text(path: &Path) -> Result<(Box<dyn BufRead + Send>, bool), IOError> {
+    // Sniff for the gzip or zstd magic rather than trusting the file name, true if it was either
+    let mut magic = [0u8; 4];
+    let read = fs::File::open(path)?.read(&mut magic)?;
+    let file = fs::File::open(path)?;
+    Ok(if read >= 2 && magic[..2] == GZIP_MAGIC {
+        (Box::new(BufReader::new(MultiGzDecoder::new(file))), true)
+    } else if read == 4 && magic == ZSTD_MAGIC {
+        (Box::new(BufReader::new(zstd::Decoder::new(file)?)), true)
+    } else {
+        (Box::new(BufReader::new(file)), false)
+    })
+}
+
+// Bulk pairs or JSON Lines from any reader, a hit at a time
+struct LineReader {
+    name: String,
+    reader: Box<dyn BufRead + Send>,
+    // Worked out from the first line when it isn't known
+    format: Option<OutputFormat>,
+    line_number: u64,
+}
+
+impl LineReader {
+    fn new(
+        name: &str,
+        reader: Box<dyn BufRead + Send>,
+        format: Option<OutputFormat>,
+    ) -> LineReader {
+        LineReader {
+            name: name.to_string(),
+            reader,
+            format,
+            line_number: 0,
+        }
+    }
+
+    fn next_hit(&mut self) -> Result<Option<Hit>, DumpError> {
+        let line = match self.read_line()? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let action = serde_json::from_str::<JsonValue>(&line)
+            .ok()
+            .and_then(|v| v.get("index").or_else(|| v.get("create")).cloned())
+            .filter(|meta| meta.get("_index").is_some() || meta.get("_id").is_some());
+        // Without a manifest, input that doesn't start with an action line is JSON Lines,
+        // with _id and _index taken from the document if it has them
+        let format = self
+            .format
+            .get_or_insert_with(|| match action {
+                Some(_) => OutputFormat::Bulk,
+                None => OutputFormat::JsonLines {
+                    id_key: Some("_id".to_string()),
+                    index_key: Some("_index".to_string()),
+                },
+            })
+            .clone();
+
+        match format {
+            OutputFormat::Bulk => {
+                let meta = action.ok_or_else(|| self.malformed("expected an action line"))?;
+                let source = self
+                    .read_line()?
+                    .ok_or_else(|| self.malformed("action line without a source"))?;
+                let field = |key: &str| meta[key].as_str().map(|value| value.to_string());
+                Ok(Some(Hit {
+                    index: field("_index").unwrap_or_default(),
+                    id: field("_id").unwrap_or_default(),
+                    routing: field("routing").or_else(|| field("_routing")),
+                    source: Some(self.raw(source)?),
+                    sort: None,
+                }))
+            }
+            OutputFormat::JsonLines { id_key, index_key } => {
+                let mut source: Map<String, JsonValue> = serde_json::from_str(&line)
+                    .map_err(|_| self.malformed("expected a JSON document"))?;
+                // The keys were added when the dump was written, they're not part of _source
+                let mut take = |key: Option<String>| match key {
+                    Some(key) => match source.remove(&key) {
+                        Some(JsonValue::String(value)) => value,
+                        _ => String::new(),
+                    },
+                    None => String::new(),
+                };
+                let id = take(id_key);
+                let index = take(index_key);
+                let source = serde_json::to_string(&source)?;
+                Ok(Some(Hit {
+                    index,
+                    id,
+                    routing: None,
+                    source: Some(self.raw(source)?),
+                    sort: None,
+                }))
+            }
+            _ => Err(self.malformed("only Bulk and JSON Lines dumps can be read back")),
+        }
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>, DumpError> {
+        // Blank lines, such as a trailing one, carry nothing
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            let trimmed = line.trim_end_matches(['\n', '\r']);
+            if !trimmed.trim().is_empty() {
+                return Ok(Some(trimmed.to_string()));
+            }
+        }
+    }
+
+    fn raw(&self, source: String) -> Result<Box<RawValue>, DumpError> {
+        RawValue::from_string(source).map_err(|_| self.malformed("expected a JSON source"))
+    }
+
+    fn malformed(&self, message: &str) -> DumpError {
+        DumpError::Source(format!("{}:{}: {}", self.name, self.line_number, message))
+    }
+}
+
+pub struct DumpSource {
+    // Files still to read, with the format each was written in when there's a manifest
+    files: VecDeque<(PathBuf, Option<OutputFormat>)>,
+    current: Option<LineReader>,
+    cluster: ClusterInfo,
+    expected: Option<u64>,
+    // Where an S3 dump was downloaded to, removed with the source
+    download: Option<PathBuf>,
+}
+
+impl DumpSource {
+    pub fn open(path: &Path, allow_partial: bool) -> Result<DumpSource, DumpError> {
+        // A manifest brings every artifact it lists, checked against their checksums first.
+        // A partial dump is only read back when the caller says so, as verify refuses it too.
+        if !path.to_string_lossy().ends_with("manifest.json") {
+            return Ok(DumpSource {
+                files: vec![(path.to_path_buf(), None)].into(),
+                current: None,
+                cluster: ClusterInfo::default(),
+                expected: None,
+                download: None,
+            });
+        }
+        let manifest = Manifest::load(&path.to_string_lossy())?;
+        if !manifest.complete && !allow_partial {
+            return Err(DumpError::Source(format!(
+                "{} is a partial dump, some slices failed when it was written",
+                path.display()
+            )));
+        }
+        if !matches!(
+            manifest.format,
+            OutputFormat::Bulk | OutputFormat::JsonLines { .. }
+        ) {
+            return Err(DumpError::Source(format!(
+                "{} is a {} dump, only Bulk and JSON Lines dumps can be read back",
+                path.display(),
+                manifest.format.extension()
+            )));
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        manifest.verify_dir(dir)?;
+
+        // Dead letters are JSON Lines whatever the dump's format, with their _id and _index
+        let dead_letters = OutputFormat::JsonLines {
+            id_key: Some("_id".to_string()),
+            index_key: Some("_index".to_string()),
+        };
+        let files = manifest
+            .artifacts
+            .iter()
+            .map(|artifact| {
+                let format = match artifact.dead_letter {
+                    true => dead_letters.clone(),
+                    false => manifest.format.clone(),
+                };
+                (dir.join(&artifact.name), Some(format))
+            })
+            .collect();
+        Ok(DumpSource {
+            files,
+            current: None,
+            cluster: manifest.cluster,
+            expected: Some(manifest.documents),
+            download: None,
+        })
+    }
+
+    pub fn from_s3(
+        bucket: &S3Bucket,
+        manifest_key: &str,
+        allow_partial: bool,
+    ) -> Result<DumpSource, DumpError> {
+        // Pulled down whole first, like verify_s3, then read like a disk dump
+        let dir = std::env::temp_dir().join(format!("tana-source-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let opened = verify::download_dump(bucket, manifest_key, &dir)
+            .and_then(|manifest_path| DumpSource::open(&manifest_path, allow_partial));
+        match opened {
+            Ok(mut source) => {
+                source.download = Some(dir);
+                Ok(source)
+            }
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                Err(e)
+            }
+        }
+    }
+
+    fn next_hit(&mut self) -> Result<Option<Hit>, DumpError> {
+        loop {
+            if let Some(reader) = self.current.as_mut() {
+                if let Some(hit) = reader.next_hit()? {
+                    return Ok(Some(hit));
+                }
+            }
+            let (path, format) = match self.files.pop_front() {
+                Some(file) => file,
+                None => return Ok(None),
+            };
+            println!("Reading {}", path.display());
+            let (reader, _) = open_text(&path)?;
+            self.current = Some(LineReader::new(&path.to_string_lossy(), reader, format));
+        }
+    }
+}
+
+#[async_trait]
+impl Source for DumpSource {
+    async fn next_page(&mut self) -> Result<Vec<Hit>, DumpError> {
+        let mut hits = vec![];
+        while hits.len() < PAGE_SIZE {
+            match self.next_hit()? {
+                Some(hit) => hits.push(hit),
+                None => break,
+            }
+        }
+        Ok(hits)
+    }
+
+    fn cluster(&self) -> ClusterInfo {
+        self.cluster.clone()
+    }
+
+    fn expected(&self) -> Option<u64> {
+        self.expected
+    }
+}
+
+impl Drop for DumpSource {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.download {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+pub struct NdjsonSource {
+    lines: LineReader,
+}
+
+impl NdjsonSource {
+    pub fn new<R: BufRead + Send + 'static>(name: &str, reader: R) -> NdjsonSource {
+        // Bulk pairs or one document per line, e.g. from stdin
+        NdjsonSource {
+            lines: LineReader::new(name, Box::new(reader), None),
+        }
+    }
+}
+
+#[async_trait]
+impl Source for NdjsonSource {
+    async fn next_page(&mut self) -> Result<Vec<Hit>, DumpError> {
+        let mut hits = vec![];
+        while hits.len() < PAGE_SIZE {
+            match self.lines.next_hit()? {
+                Some(hit) => hits.push(hit),
+                None => break,
+            }
+        }
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dumper::manifest::{Artifact, FORMAT_VERSION};
+    use crate::dumper::sink::{DiskSink, Sink};
+    use crate::dumper::transform::Pipeline;
+    use chrono::Utc;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn reads_bulk_and_json_lines() {
+        let bulk = "{\"index\":{\"_index\":\"logs\",\"_id\":\"1\"}}\n{\"n\":1}\n\n\
+                    {\"index\":{\"_index\":\"logs\",\"_id\":\"2\",\"routing\":\"eu\"}}\n{\"n\":2}\n";
+        let mut source = NdjsonSource::new("stdin", Cursor::new(bulk));
+        let hits = source.next_page().await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[1].id, "2");
+        assert_eq!(hits[1].routing.as_deref(), Some("eu"));
+        assert_eq!(hits[1].source.as_ref().unwrap().get(), "{\"n\":2}");
+        assert!(source.next_page().await.unwrap().is_empty());
+
+        let lines = "{\"_id\":\"7\",\"user\":\"alice\"}\n{\"user\":\"bob\"}\n";
+        let hits = NdjsonSource::new("stdin", Cursor::new(lines))
+            .next_page()
+            .await
+            .unwrap();
+        assert_eq!((hits[0].id.as_str(), hits[0].index.as_str()), ("7", ""));
+        assert_eq!(
+            hits[0].source.as_ref().unwrap().get(),
+            "{\"user\":\"alice\"}"
+        );
+        assert_eq!(hits[1].id, "");
+
+        // Compressed dump files are read by their magic, whatever they're called
+        let dir = std::env::temp_dir().join(format!("tana-source-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("logs.json");
+        let mut encoder = GzEncoder::new(fs::File::create(&path).unwrap(), Compression::fast());
+        encoder.write_all(bulk.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let hits = DumpSource::open(&path, false)
+            .unwrap()
+            .next_page()
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].index, "logs");
+
+        let torn = "{\"index\":{\"_index\":\"logs\",\"_id\":\"1\"}}\n";
+        assert!(NdjsonSource::new("stdin", Cursor::new(torn))
+            .next_page()
+            .await
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_a_partial_dump_unless_allowed() {
+        let dir = std::env::temp_dir().join(format!("tana-source-partial-{}", std::process::id()));
+        let mut sink = DiskSink::new(&dir);
+        sink.create("logs.json", None).await.unwrap();
+        sink.write(b"{\"index\":{\"_index\":\"logs\",\"_id\":\"1\"}}\n{}\n")
+            .await
+            .unwrap();
+        let artifact = Artifact::from_stored(sink.flush().await.unwrap(), 0);
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            tana_version: env!("CARGO_PKG_VERSION").to_string(),
+            cluster: ClusterInfo::default(),
+            index: "logs".to_string(),
+            query: None,
+            source_filter: None,
+            transforms: Pipeline::default(),
+            redactions: vec![],
+            script: None,
+            slices: 2,
+            documents: 1,
+            complete: false,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            format: OutputFormat::Bulk,
+            artifacts: vec![artifact],
+        };
+        let content = serde_json::to_vec_pretty(&manifest).unwrap();
+        sink.finish("logs.manifest.json", &content).await.unwrap();
+
+        let path = dir.join("logs.manifest.json");
+        let refused = DumpSource::open(&path, false);
+        let allowed = DumpSource::open(&path, true).map(|source| source.expected());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(refused, Err(DumpError::Source(_))));
+        assert_eq!(allowed.unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,282 @@
+use crate::dumper::error::DumpError;
+use crate::dumper::partition;
+use chrono::format::{Item, StrftimeItems};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    // e.g. {"rename": {"field": "msg", "to": "message"}}
+    Rename {
+        field: String,
+        to: String,
+    },
+    Remove {
+        field: String,
+    },
+    // _index and _id can be set like any field, e.g. to restore into another index
+    Set {
+        field: String,
+        value: JsonValue,
+    },
+    Copy {
+        field: String,
+        to: String,
+    },
+    Lowercase {
+        field: String,
+    },
+    // A strftime pattern such as %Y-%m-%d, or epoch_millis or epoch_second
+    DateFormat {
+        field: String,
+        format: String,
+    },
+    // Leave the document out of the dump altogether
+    Drop {
+        #[serde(rename = "if")]
+        condition: Condition,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub field: String,
+    // With neither, the condition is that the field exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<JsonValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exists: Option<bool>,
+}
+
+impl Condition {
+    fn holds(&self, document: &Document) -> bool {
+        let value = document.get(&self.field).filter(|value| !value.is_null());
+        match (&self.equals, self.exists) {
+            (Some(equals), _) => value.as_ref() == Some(equals),
+            (None, exists) => value.is_some() == exists.unwrap_or(true),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub index: String,
+    pub id: String,
+    pub source: JsonValue,
+}
+
+impl Document {
+    fn get(&self, field: &str) -> Option<JsonValue> {
+        match field {
+            "_index" => Some(JsonValue::from(self.index.clone())),
+            "_id" => Some(JsonValue::from(self.id.clone())),
+            field => partition::field_value(&self.source, field).cloned(),
+        }
+    }
+
+    fn set(&mut self, field: &str, value: JsonValue) {
+        let text = |value: JsonValue| match value {
+            JsonValue::String(text) => text,
+            value => value.to_string(),
+        };
+        match field {
+            "_index" => self.index = text(value),
+            "_id" => self.id = text(value),
+            field => set_path(&mut self.source, field, value),
+        }
+    }
+
+    fn remove(&mut self, field: &str) -> Option<JsonValue> {
+        // Every document keeps an _index and _id
+        match field {
+            "_index" | "_id" => self.get(field),
+            field => remove_path(&mut self.source, field),
+        }
+    }
+}
+
+fn set_path(source: &mut JsonValue, field: &str, value: JsonValue) {
+    // A literal dotted key is replaced where it is, otherwise objects are made along the path
+    let object = match source.as_object_mut() {
+        Some(object) => object,
+        None => return,
+    };
+    if object.contains_key(field) {
+        object.insert(field.to_string(), value);
+        return;
+    }
+    match field.split_once('.') {
+        None => {
+            object.insert(field.to_string(), value);
+        }
+        Some((head, rest)) => {
+            let child = object
+                .entry(head.to_string())
+                .or_insert_with(|| JsonValue::Object(Map::new()));
+            if !child.is_object() {
+                *child = JsonValue::Object(Map::new());
+            }
+            set_path(child, rest, value);
+        }
+    }
+}
+
+fn remove_path(source: &mut JsonValue, field: &str) -> Option<JsonValue> {
+    let object = source.as_object_mut()?;
+    if let Some(value) = object.remove(field) {
+        return Some(value);
+    }
+    let (head, rest) = field.split_once('.')?;
+    remove_path(object.get_mut(head)?, rest)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Pipeline {
+    pub steps: Vec<Transform>,
+}
+
+impl Pipeline {
+    pub fn load(path: &Path) -> Result<Pipeline, DumpError> {
+        // A JSON array of steps, applied in order
+        let pipeline: Pipeline = serde_json::from_str(&fs::read_to_string(path)?)?;
+        pipeline.validate()?;
+        Ok(pipeline)
+    }
+
+    pub fn validate(&self) -> Result<(), DumpError> {
+        // Steps can be built in code too, so a dump checks them again before it starts
+        for step in &self.steps {
+            if let Transform::DateFormat { format, .. } = step {
+                let epoch = format == "epoch_millis" || format == "epoch_second";
+                if !epoch && StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(invalid_format(format));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn apply(&self, document: &mut Document) -> Result<bool, DumpError> {
+        // False once a step drops the document. Steps on a missing field do nothing
+        for step in &self.steps {
+            match step {
+                Transform::Rename { field, to } => {
+                    if let Some(value) = document.remove(field) {
+                        document.set(to, value);
+                    }
+                }
+                Transform::Remove { field } => {
+                    document.remove(field);
+                }
+                Transform::Set { field, value } => document.set(field, value.clone()),
+                Transform::Copy { field, to } => {
+                    if let Some(value) = document.get(field) {
+                        document.set(to, value);
+                    }
+                }
+                Transform::Lowercase { field } => {
+                    if let Some(JsonValue::String(text)) = document.get(field) {
+                        document.set(field, JsonValue::from(text.to_lowercase()));
+                    }
+                }
+                Transform::DateFormat { field, format } => {
+                    // A value that isn't a date is left as it is
+                    let at = document
+                        .get(field)
+                        .and_then(|value| partition::timestamp(&value));
+                    if let Some(at) = at {
+                        let value = match format.as_str() {
+                            "epoch_millis" => JsonValue::from(at.timestamp_millis()),
+                            "epoch_second" => JsonValue::from(at.timestamp()),
+                            format => {
+                                let mut text = String::new();
+                                write!(text, "{}", at.format(format))
+                                    .map_err(|_| invalid_format(format))?;
+                                JsonValue::from(text)
+                            }
+                        };
+                        document.set(field, value);
+                    }
+                }
+                Transform::Drop { condition } => {
+                    if condition.holds(document) {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn invalid_format(format: &str) -> DumpError {
+    DumpError::Transform(format!("'{}' is not a valid date format", format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn applies_steps_in_order() {
+        let pipeline: Pipeline = serde_json::from_value(json!([
+            { "rename": { "field": "msg", "to": "message" } },
+            { "remove": { "field": "winlog.event_data" } },
+            { "set": { "field": "backup_source", "value": "prod" } },
+            { "set": { "field": "_index", "value": "logs-archive" } },
+            { "copy": { "field": "host.name", "to": "hostname" } },
+            { "lowercase": { "field": "hostname" } },
+            { "date_format": { "field": "@timestamp", "format": "%Y-%m-%d" } },
+            { "drop": { "if": { "field": "level", "equals": "debug" } } },
+        ]))
+        .unwrap();
+
+        let mut document = Document {
+            index: "logs".to_string(),
+            id: "1".to_string(),
+            source: json!({
+                "msg": "hello",
+                "@timestamp": "2020-04-20T13:45:00Z",
+                "host": { "name": "WEB-1" },
+                "winlog": { "channel": "Security", "event_data": { "LogonType": "3" } },
+            }),
+        };
+        assert!(pipeline.apply(&mut document).unwrap());
+        assert_eq!(document.index, "logs-archive");
+        assert_eq!(
+            document.source,
+            json!({
+                "message": "hello",
+                "@timestamp": "2020-04-20",
+                "host": { "name": "WEB-1" },
+                "hostname": "web-1",
+                "winlog": { "channel": "Security" },
+                "backup_source": "prod",
+            })
+        );
+
+        document.source["level"] = json!("debug");
+        assert!(!pipeline.apply(&mut document).unwrap());
+
+        // Built in code rather than loaded, so nothing checked the pattern beforehand
+        let pipeline = Pipeline {
+            steps: vec![Transform::DateFormat {
+                field: "@timestamp".to_string(),
+                format: "%Y-%Q".to_string(),
+            }],
+        };
+        assert!(pipeline.validate().is_err());
+        document.source["@timestamp"] = json!("2020-04-20T13:45:00Z");
+        assert!(pipeline.apply(&mut document).is_err());
+    }
+}
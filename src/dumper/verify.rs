@@ -0,0 +1,660 @@
+use crate::dumper::avro;
+use crate::dumper::delimited::{CsvOptions, Quoting};
+use crate::dumper::error::DumpError;
+use crate::dumper::format::OutputFormat;
+use crate::dumper::manifest::Manifest;
+use crate::dumper::projection::SourceFilter;
+use crate::dumper::source;
+use arrow::array::{Array, StringArray};
+use csv::ReaderBuilder;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use s3::bucket::Bucket as S3Bucket;
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::path::Path;
+
+// Don't drown the summary, the counts say how bad it is
+const MAX_ERRORS: usize = 20;
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub files: Vec<String>,
+    // None when verifying a bare dump file without a manifest
+    pub checksums_ok: Option<bool>,
+    pub expected_documents: Option<u64>,
+    // False for a dump kept with failed slices, None without a manifest
+    pub complete: Option<bool>,
+    // The manifest's _source filter, documents only hold some of their fields
+    pub source_filter: Option<SourceFilter>,
+    pub documents: BTreeMap<String, u64>,
+    pub duplicate_ids: u64,
+    pub malformed_lines: u64,
+    pub truncated: bool,
+    pub errors: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn total_documents(&self) -> u64 {
+        self.documents.values().sum()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.checksums_ok != Some(false)
+            && self.complete != Some(false)
+            && self
+                .expected_documents
+                .is_none_or(|expected| expected == self.total_documents())
+            && self.duplicate_ids == 0
+            && self.malformed_lines == 0
+            && !self.truncated
+    }
+
+    fn error(&mut self, message: String) {
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(message);
+        }
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Verified {}", self.files.join(", "))?;
+        match self.checksums_ok {
+            Some(true) => writeln!(f, "  checksums: OK")?,
+            Some(false) => writeln!(f, "  checksums: FAILED")?,
+            None => writeln!(f, "  checksums: not checked, no manifest")?,
+        }
+        if self.complete == Some(false) {
+            writeln!(f, "  PARTIAL: some slices failed when it was dumped")?;
+        }
+        if let Some(filter) = &self.source_filter {
+            writeln!(
+                f,
+                "  partial documents: includes [{}], excludes [{}]",
+                filter.includes.join(", "),
+                filter.excludes.join(", ")
+            )?;
+        }
+        for (index, documents) in &self.documents {
+            writeln!(f, "  {}: {} documents", index, documents)?;
+        }
+        match self.expected_documents {
+            Some(expected) => writeln!(
+                f,
+                "  total: {} documents, manifest says {}",
+                self.total_documents(),
+                expected
+            )?,
+            None => writeln!(f, "  total: {} documents", self.total_documents())?,
+        }
+        writeln!(f, "  duplicate ids: {}", self.duplicate_ids)?;
+        writeln!(f, "  malformed lines: {}", self.malformed_lines)?;
+        if self.truncated {
+            writeln!(f, "  TRUNCATED: the compressed stream ends early")?;
+        }
+        for error in &self.errors {
+            writeln!(f, "  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn verify_manifest(manifest_path: &Path) -> Result<VerifyReport, DumpError> {
+    // Artifacts are named relative to the manifest
+    let manifest = Manifest::load(&manifest_path.to_string_lossy())?;
+    let dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut report = VerifyReport {
+        expected_documents: Some(manifest.documents),
+        complete: Some(manifest.complete),
+        source_filter: manifest.source_filter.clone(),
+        ..Default::default()
+    };
+    if !manifest.complete {
+        report.error("Manifest marks this dump as partial, some slices failed".to_string());
+    }
+
+    report.checksums_ok = Some(match manifest.verify_dir(dir) {
+        Ok(()) => true,
+        Err(e) => {
+            report.error(e.to_string());
+            false
+        }
+    });
+
+    // Dead letters are JSON Lines whatever the dump's format, and their documents count too
+    let dead_letters = Manifest {
+        format: OutputFormat::JsonLines {
+            id_key: Some("_id".to_string()),
+            index_key: Some("_index".to_string()),
+        },
+        ..manifest.clone()
+    };
+    let mut seen = HashSet::new();
+    for artifact in &manifest.artifacts {
+        let path = dir.join(&artifact.name);
+        report.files.push(path.to_string_lossy().to_string());
+        let manifest = if artifact.dead_letter {
+            &dead_letters
+        } else {
+            &manifest
+        };
+        check_lines(&path, Some(manifest), &mut report, &mut seen)?;
+    }
+    Ok(report)
+}
+
+pub fn verify_dump(path: &Path) -> Result<VerifyReport, DumpError> {
+    // A dump file on its own, all we can check is the content
+    let mut report = VerifyReport {
+        files: vec![path.to_string_lossy().to_string()],
+        ..Default::default()
+    };
+    check_lines(path, None, &mut report, &mut HashSet::new())?;
+    Ok(report)
+}
+
+pub fn verify_s3(bucket: &S3Bucket, manifest_key: &str) -> Result<VerifyReport, DumpError> {
+    // Pull everything down next to each other and verify it like a disk dump
+    let dir = std::env::temp_dir().join(format!("tana-verify-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let result = download_dump(bucket, manifest_key, &dir)
+        .and_then(|manifest_path| verify_manifest(&manifest_path));
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+pub(crate) fn download_dump(
+    bucket: &S3Bucket,
+    manifest_key: &str,
+    dir: &Path,
+) -> Result<std::path::PathBuf, DumpError> {
+    let manifest_name = Path::new(manifest_key)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| manifest_key.to_string());
+    let manifest_path = dir.join(&manifest_name);
+    download(bucket, manifest_key, &manifest_path)?;
+
+    // Artifact keys sit next to the manifest key
+    let prefix = &manifest_key[..manifest_key.len() - manifest_name.len()];
+    let manifest = Manifest::load(&manifest_path.to_string_lossy())?;
+    for artifact in &manifest.artifacts {
+        let key = format!("{}{}", prefix, artifact.name);
+        println!("GET {} from S3.", key);
+        download(bucket, &key, &dir.join(&artifact.name))?;
+    }
+    Ok(manifest_path)
+}
+
+fn download(bucket: &S3Bucket, key: &str, path: &Path) -> Result<(), DumpError> {
+    // Partitioned dumps keep their parts in subdirectories
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::File::create(path)?;
+    let code = bucket
+        .get_object_stream(key, &mut file)
+        .map_err(|e| DumpError::S3(e.to_string()))?;
+    if code != 200 {
+        return Err(DumpError::S3(format!(
+            "Error getting {} from S3: {}",
+            key, code
+        )));
+    }
+    Ok(())
+}
+
+fn check_lines(
+    path: &Path,
+    manifest: Option<&Manifest>,
+    report: &mut VerifyReport,
+    seen: &mut HashSet<(String, String)>,
+) -> Result<(), DumpError> {
+    // Sniff for the Parquet or Avro magic rather than trusting the file name
+    let mut file = fs::File::open(path)?;
+    let mut magic = [0u8; 4];
+    let read = file.read(&mut magic)?;
+    let default_index = manifest
+        .map(|manifest| manifest.index.as_str())
+        .unwrap_or("");
+    if read == 4 && &magic == b"PAR1" {
+        return check_parquet(path, default_index, report, seen);
+    }
+    if read == 4 && &magic == b"Obj\x01" {
+        return check_avro(path, report, seen);
+    }
+    let (reader, compressed) = source::open_text(path)?;
+
+    // CSV can't be told apart by its first line, a bare file goes by its extension
+    let format = match manifest {
+        Some(manifest) => Some(manifest.format.clone()),
+        None => csv_by_extension(path).map(OutputFormat::Csv),
+    };
+    if let Some(OutputFormat::Csv(options)) = &format {
+        return check_csv(
+            reader,
+            path,
+            options,
+            compressed,
+            default_index,
+            report,
+            seen,
+        );
+    }
+
+    // Without a manifest, a dump that doesn't start with an action line is JSON Lines
+    let mut lines = reader.lines().peekable();
+    let format = match format {
+        Some(format) => format,
+        None => match lines.peek() {
+            Some(Ok(line)) if parse_action(line).is_none() => OutputFormat::JsonLines {
+                id_key: None,
+                index_key: None,
+            },
+            _ => OutputFormat::Bulk,
+        },
+    };
+
+    // Bulk lines come in pairs: an action line, then the document source
+    let mut action: Option<(String, String)> = None;
+    let mut line_number = 0;
+    for line in lines {
+        line_number += 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(e) if compressed && e.kind() == ErrorKind::UnexpectedEof => {
+                report.truncated = true;
+                report.error(format!(
+                    "{}: gzip member truncated after line {}",
+                    path.display(),
+                    line_number - 1
+                ));
+                return Ok(());
+            }
+            Err(e) => {
+                report.malformed_lines += 1;
+                report.error(format!("{}:{}: {}", path.display(), line_number, e));
+                return Ok(());
+            }
+        };
+
+        if let OutputFormat::JsonLines { id_key, index_key } = &format {
+            // Each line is a whole document, ids only known if they were kept
+            match serde_json::from_str::<JsonValue>(&line) {
+                Ok(source @ JsonValue::Object(_)) => {
+                    let field = |key: &Option<String>| {
+                        key.as_ref()
+                            .and_then(|key| source[key.as_str()].as_str())
+                            .map(|value| value.to_string())
+                    };
+                    let index = field(index_key).unwrap_or_else(|| default_index.to_string());
+                    *report.documents.entry(index.clone()).or_insert(0) += 1;
+                    if let Some(id) = field(id_key) {
+                        if !seen.insert((index, id.clone())) {
+                            report.duplicate_ids += 1;
+                            report.error(format!(
+                                "{}:{}: duplicate id {}",
+                                path.display(),
+                                line_number,
+                                id
+                            ));
+                        }
+                    }
+                }
+                _ => {
+                    report.malformed_lines += 1;
+                    report.error(format!(
+                        "{}:{}: expected a JSON document",
+                        path.display(),
+                        line_number
+                    ));
+                }
+            }
+            continue;
+        }
+
+        match action.take() {
+            None => match parse_action(&line) {
+                Some(parsed) => action = Some(parsed),
+                None => {
+                    report.malformed_lines += 1;
+                    report.error(format!(
+                        "{}:{}: expected an action line",
+                        path.display(),
+                        line_number
+                    ));
+                }
+            },
+            Some((index, id)) => match serde_json::from_str::<JsonValue>(&line) {
+                Ok(JsonValue::Object(_)) => {
+                    *report.documents.entry(index.clone()).or_insert(0) += 1;
+                    if !id.is_empty() && !seen.insert((index, id.clone())) {
+                        report.duplicate_ids += 1;
+                        report.error(format!(
+                            "{}:{}: duplicate id {}",
+                            path.display(),
+                            line_number,
+                            id
+                        ));
+                    }
+                }
+                _ => {
+                    report.malformed_lines += 1;
+                    report.error(format!(
+                        "{}:{}: expected a JSON source",
+                        path.display(),
+                        line_number
+                    ));
+                }
+            },
+        }
+    }
+
+    if action.is_some() {
+        report.malformed_lines += 1;
+        report.error(format!(
+            "{}: action line without a source at the end",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+fn check_parquet(
+    path: &Path,
+    default_index: &str,
+    report: &mut VerifyReport,
+    seen: &mut HashSet<(String, String)>,
+) -> Result<(), DumpError> {
+    // A row per document, the _index and _id columns say which
+    let batches = match fs::File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| {
+            ParquetRecordBatchReaderBuilder::try_new(file)
+                .and_then(|builder| builder.build())
+                .map_err(|e| e.to_string())
+        }) {
+        Ok(batches) => batches,
+        Err(e) => {
+            report.malformed_lines += 1;
+            report.error(format!(
+                "{}: unreadable Parquet file: {}",
+                path.display(),
+                e
+            ));
+            return Ok(());
+        }
+    };
+
+    let mut row = 0;
+    for batch in batches {
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(e) => {
+                report.malformed_lines += 1;
+                report.error(format!("{}: row {}: {}", path.display(), row, e));
+                return Ok(());
+            }
+        };
+        let column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .and_then(|column| column.as_any().downcast_ref::<StringArray>().cloned())
+        };
+        let (indices, ids) = (column("_index"), column("_id"));
+        for i in 0..batch.num_rows() {
+            row += 1;
+            let index = indices
+                .as_ref()
+                .filter(|indices| indices.is_valid(i))
+                .map(|indices| indices.value(i).to_string())
+                .unwrap_or_else(|| default_index.to_string());
+            *report.documents.entry(index.clone()).or_insert(0) += 1;
+            let id = match ids.as_ref().filter(|ids| ids.is_valid(i)) {
+                Some(ids) => ids.value(i).to_string(),
+                None => continue,
+            };
+            if !seen.insert((index, id.clone())) {
+                report.duplicate_ids += 1;
+                report.error(format!(
+                    "{}: row {}: duplicate id {}",
+                    path.display(),
+                    row,
+                    id
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_avro(
+    path: &Path,
+    report: &mut VerifyReport,
+    seen: &mut HashSet<(String, String)>,
+) -> Result<(), DumpError> {
+    // Every block has to decompress and decode against the embedded schema
+    let mut duplicates = vec![];
+    let read = avro::read_ids(path, |index, id| {
+        *report.documents.entry(index.to_string()).or_insert(0) += 1;
+        if !id.is_empty() && !seen.insert((index.to_string(), id.to_string())) {
+            duplicates.push(id.to_string());
+        }
+    });
+    for id in duplicates {
+        report.duplicate_ids += 1;
+        report.error(format!("{}: duplicate id {}", path.display(), id));
+    }
+    if let Err(e) = read {
+        report.malformed_lines += 1;
+        report.error(format!("{}: unreadable Avro file: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+fn csv_by_extension(path: &Path) -> Option<CsvOptions> {
+    // The header row names the columns
+    let name = path.to_string_lossy();
+    let name = name.trim_end_matches(".gz");
+    if name.ends_with(".csv") {
+        Some(CsvOptions::new(vec![], ','))
+    } else if name.ends_with(".tsv") {
+        Some(CsvOptions::new(vec![], '\t'))
+    } else {
+        None
+    }
+}
+
+fn check_csv<R: Read>(
+    reader: R,
+    path: &Path,
+    options: &CsvOptions,
+    compressed: bool,
+    default_index: &str,
+    report: &mut VerifyReport,
+    seen: &mut HashSet<(String, String)>,
+) -> Result<(), DumpError> {
+    // Rows must all have as many cells as the header, ids are only known if _id was a field
+    let mut rows = ReaderBuilder::new()
+        .delimiter(options.delimiter as u8)
+        .has_headers(options.header)
+        .quoting(options.quoting != Quoting::Never)
+        .from_reader(reader);
+    let fields: Vec<String> = match options.header {
+        true => match rows.headers() {
+            Ok(headers) => headers.iter().map(|field| field.to_string()).collect(),
+            Err(e) => {
+                report.malformed_lines += 1;
+                report.error(format!("{}: unreadable header: {}", path.display(), e));
+                return Ok(());
+            }
+        },
+        false => options.fields.clone(),
+    };
+    let column = |name: &str| fields.iter().position(|field| field == name);
+    let (index_column, id_column) = (column("_index"), column("_id"));
+
+    for row in rows.records() {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                let line = e.position().map(|at| at.line()).unwrap_or(0);
+                if let csv::ErrorKind::Io(io) = e.kind() {
+                    if compressed && io.kind() == ErrorKind::UnexpectedEof {
+                        report.truncated = true;
+                        report.error(format!(
+                            "{}: gzip member truncated after line {}",
+                            path.display(),
+                            line
+                        ));
+                    } else {
+                        report.malformed_lines += 1;
+                        report.error(format!("{}: {}", path.display(), e));
+                    }
+                    return Ok(());
+                }
+                report.malformed_lines += 1;
+                report.error(format!("{}:{}: {}", path.display(), line, e));
+                continue;
+            }
+        };
+        let line = row.position().map(|at| at.line()).unwrap_or(0);
+        let cell = |column: Option<usize>| column.and_then(|column| row.get(column));
+        let index = cell(index_column).unwrap_or(default_index).to_string();
+        *report.documents.entry(index.clone()).or_insert(0) += 1;
+        if let Some(id) = cell(id_column).filter(|id| !id.is_empty()) {
+            if !seen.insert((index, id.to_string())) {
+                report.duplicate_ids += 1;
+                report.error(format!("{}:{}: duplicate id {}", path.display(), line, id));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_action(line: &str) -> Option<(String, String)> {
+    let v: JsonValue = serde_json::from_str(line).ok()?;
+    let meta = v.get("index").or_else(|| v.get("create"))?;
+    // No _id is fine, the document gets a new one when it's restored
+    Some((
+        meta["_index"].as_str()?.to_string(),
+        meta["_id"].as_str().unwrap_or("").to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dumper::manifest::{Artifact, ClusterInfo, FORMAT_VERSION};
+    use crate::dumper::sink::{DiskSink, Sink};
+    use crate::dumper::transform::Pipeline;
+    use chrono::Utc;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn dump(documents: usize) -> String {
+        (0..documents)
+            .map(|i| {
+                format!(
+                    "{{\"index\":{{\"_index\":\"logs\",\"_id\":\"{}\"}}}}\n{{\"n\":{}}}\n",
+                    i, i
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_documents_and_duplicates() {
+        let path =
+            std::env::temp_dir().join(format!("tana-verify-test-{}.json", std::process::id()));
+        fs::write(&path, format!("{}{}", dump(3), dump(1))).unwrap();
+        let report = verify_dump(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.documents["logs"], 4);
+        assert_eq!(report.duplicate_ids, 1);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn detects_json_lines_without_a_manifest() {
+        let path =
+            std::env::temp_dir().join(format!("tana-verify-test-{}.jsonl", std::process::id()));
+        fs::write(&path, "{\"n\":1}\n{\"n\":2}\n[]\n").unwrap();
+        let report = verify_dump(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.total_documents(), 2);
+        assert_eq!(report.malformed_lines, 1);
+    }
+
+    #[test]
+    fn counts_csv_rows_by_header() {
+        let path =
+            std::env::temp_dir().join(format!("tana-verify-test-{}.csv", std::process::id()));
+        fs::write(&path, "_id,user\n1,\"alice\nbob\"\n2,carol\n1,dave\n3\n").unwrap();
+        let report = verify_dump(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.documents[""], 3);
+        assert_eq!(report.duplicate_ids, 1);
+        assert_eq!(report.malformed_lines, 1);
+    }
+
+    #[test]
+    fn detects_truncated_gzip() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(dump(1000).as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let path = std::env::temp_dir().join(format!("tana-verify-test-{}.gz", std::process::id()));
+        fs::write(&path, &compressed[..compressed.len() / 2]).unwrap();
+        let report = verify_dump(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(report.truncated);
+        assert!(!report.is_ok());
+    }
+
+    #[tokio::test]
+    async fn fails_a_partial_dump() {
+        let dir = std::env::temp_dir().join(format!("tana-verify-partial-{}", std::process::id()));
+        let mut sink = DiskSink::new(&dir);
+        sink.create("logs.json", None).await.unwrap();
+        sink.write(dump(2).as_bytes()).await.unwrap();
+        let artifact = Artifact::from_stored(sink.flush().await.unwrap(), 0);
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            tana_version: env!("CARGO_PKG_VERSION").to_string(),
+            cluster: ClusterInfo::default(),
+            index: "logs".to_string(),
+            query: None,
+            source_filter: None,
+            transforms: Pipeline::default(),
+            redactions: vec![],
+            script: None,
+            slices: 2,
+            documents: 2,
+            complete: false,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            format: OutputFormat::Bulk,
+            artifacts: vec![artifact],
+        };
+        let content = serde_json::to_vec_pretty(&manifest).unwrap();
+        sink.finish("logs.manifest.json", &content).await.unwrap();
+        let manifest_path = dir.join("logs.manifest.json");
+        let report = verify_manifest(&manifest_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Every checksum and count matches, it's still not the whole index
+        assert_eq!(report.checksums_ok, Some(true));
+        assert_eq!(report.total_documents(), 2);
+        assert_eq!(report.complete, Some(false));
+        assert!(!report.is_ok());
+    }
+}
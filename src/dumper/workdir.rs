@@ -0,0 +1,76 @@
+use chrono::Utc;
+use std::fs;
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Tells apart runs started in the same millisecond by the same process
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+// One directory per run holds its slice files, so nothing else can be swept up with them
+pub struct WorkDir {
+    path: PathBuf,
+}
+
+impl WorkDir {
+    pub fn create(parent: &Path, index: &str) -> Result<WorkDir, IOError> {
+        // Timestamp, pid and a counter keep concurrent and repeated runs apart
+        let name = format!(
+            ".tana-{}-{}-{}-{}",
+            index,
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = parent.join(name);
+        fs::create_dir_all(&path)?;
+        Ok(WorkDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn slice_file(&self, slice_id: i64) -> PathBuf {
+        self.path.join(format!("slice-{}.json", slice_id))
+    }
+
+    pub fn remove(&self) -> Result<(), IOError> {
+        match fs::remove_dir_all(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        // Every early return from a dump still takes its slice files with it
+        let _ = self.remove();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_never_share_a_directory() {
+        let parent = std::env::temp_dir();
+        let first = WorkDir::create(&parent, "logs").unwrap();
+        let second = WorkDir::create(&parent, "logs").unwrap();
+        assert_ne!(first.path(), second.path());
+        assert!(first.slice_file(0).starts_with(first.path()));
+
+        fs::write(first.slice_file(0), "{}\n").unwrap();
+        first.remove().unwrap();
+        assert!(!first.path().exists());
+
+        // Dropped on the way out of a failed dump
+        let path = second.path().to_path_buf();
+        fs::write(second.slice_file(0), "{}\n").unwrap();
+        drop(second);
+        assert!(!path.exists());
+    }
+}
@@ -1,9 +1,22 @@
-use crate::dumper::Dumper;
+use clap::ArgMatches;
+use s3::bucket::Bucket as S3Bucket;
+use s3::credentials::Credentials as S3Credentials;
 use std::env;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
+use tana::dumper::{
+    self, ArrayStyle, Auth, AvroCodec, CancelToken, Compression, CsvOptions, DumpError, DumpReport,
+    DumpSource, Dumper, NdjsonSource, ObjectStyle, OutputFormat, ParquetCompression, PartLimits,
+    Partitioning, Pipeline, Quoting, Redactor, Script, ScriptLimits, ScrollOptions, Source,
+    SourceFilter, Tls, VerifyReport,
+};
 
 mod app;
-mod dumper;
+
+// 128 + SIGINT, as a shell reports an interrupted command
+const EXIT_CANCELLED: i32 = 130;
 
 #[global_allocator]
 static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
@@ -12,13 +25,17 @@ static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 async fn main() {
     // Command line arguments
     let matches = app::parse_args();
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        verify(verify_matches);
+    }
 
-    // Our Dumper
-    let created: Result<Dumper, reqwest::Error>;
-
-    // Check we have environment variables first
-    let elastic_host = env::var("ES_HOST")
-        .expect("No Elasticsearch host given. Please set ES_HOST environment variable.");
+    // Check we have environment variables first, reading a dump needs no cluster
+    let from = matches.value_of("from");
+    let elastic_host = match from {
+        Some(_) => env::var("ES_HOST").unwrap_or_default(),
+        None => env::var("ES_HOST")
+            .expect("No Elasticsearch host given. Please set ES_HOST environment variable."),
+    };
     let elastic_user = env::var("ES_USER").unwrap_or("".to_string());
     let elastic_pass = env::var("ES_PASS").unwrap_or("".to_string());
 
@@ -29,102 +46,468 @@ async fn main() {
     let index = matches
         .value_of("index")
         .expect("No index supplied. Use the '--index' argument");
-    let slices: i64 = matches
-        .value_of("concurrency")
-        .unwrap_or("3")
-        .parse()
-        .unwrap();
-    if destination == "s3" {
-        // If s3: check more environment variables
-        let s3_access_key = env::var("S3_ACCESS_KEY")
-            .expect("No AWS access key ID provided. Set the S3_ACCESS_KEY environment variable.");
-        let s3_secret_key = env::var("S3_SECRET_KEY").expect(
-            "No AWS secret access key provided. Set the S3_SECRET_KEY environment variable.",
-        );
-        // Bucket name
-        let s3_bucket_name = matches
-            .value_of("bucket")
-            .expect("No S3 bucket name provided. Use the '--bucket' argument");
-        // Region
-        let s3_region = matches
-            .value_of("region")
-            .expect("No AWS region supplied. Use the '--region' argument");
-
-        // Create an S3 dumper
-        created = Dumper::new_with_bucket(
-            &elastic_host,
-            &elastic_user,
-            &elastic_pass,
-            &s3_bucket_name,
-            &s3_access_key,
-            &s3_secret_key,
-            &s3_region,
-        );
-    } else if destination == "disk" {
-        // Create a Disk dumper
-        created = Dumper::new(&elastic_host, &elastic_user, &elastic_pass)
-    } else {
-        println!(
-            "Destination: {} is not supported. Must be [s3|disk].",
-            destination
+    let concurrency = matches.value_of("concurrency").unwrap_or("3");
+
+    // TLS is verified unless there's a CA to trust or TLS_NO_VERIFY is set
+    let tls = match matches.value_of("ca_cert") {
+        Some(path) => Tls::CaCertificate(PathBuf::from(path)),
+        None if !env::var("TLS_NO_VERIFY").unwrap_or_default().is_empty() => {
+            Tls::AcceptInvalidCerts
+        }
+        None => Tls::Verify,
+    };
+    let mut builder = Dumper::builder()
+        .hosts(&[&elastic_host])
+        .auth(Auth::Basic {
+            username: elastic_user,
+            password: elastic_pass,
+        })
+        .tls(tls);
+    if let Some(timeout) = matches.value_of("timeout") {
+        builder = builder.timeout(Duration::from_secs(
+            timeout
+                .parse()
+                .expect("Invalid '--timeout', must be a number of seconds."),
+        ));
+    }
+    if concurrency != "auto" {
+        builder = builder.concurrency(
+            concurrency
+                .parse()
+                .expect("Invalid '--concurrency', must be a number of slices or [auto]."),
         );
-        process::exit(1);
+    }
+    let mut scroll_options = ScrollOptions::default();
+    if let Some(page_size) = matches.value_of("page_size") {
+        scroll_options.page_size = page_size
+            .parse()
+            .expect("Invalid '--page-size', must be a number of documents.");
+    }
+    if let Some(keep_alive) = matches.value_of("keep_alive") {
+        scroll_options.keep_alive = keep_alive.to_string();
+    }
+    scroll_options.adaptive = matches.is_present("adaptive");
+    builder = builder.scroll_options(scroll_options);
+    if let Some(dir) = matches.value_of("output_dir") {
+        builder = builder.output_dir(dir);
     }
 
-    // Sanity check before unwrapping
-    if !created.is_ok() {
-        println!("Unable to create Elasticsearch object.");
-        process::exit(1);
+    // Now check destination, gzip or zstd layers over whichever it is
+    match destination {
+        "disk" => {}
+        "s3" => {
+            // If s3: check more environment variables
+            let bucket_name = matches
+                .value_of("bucket")
+                .expect("No S3 bucket name provided. Use the '--bucket' argument");
+            if !matches.is_present("part_size") {
+                println!("'--dest s3' needs a '--part-size', each part is uploaded from memory.");
+                process::exit(1);
+            }
+            builder = builder.bucket(s3_bucket(bucket_name, &matches));
+        }
+        _ => {
+            println!(
+                "Destination: {} is not supported. Must be [s3|disk].",
+                destination
+            );
+            process::exit(1);
+        }
+    }
+    if matches.is_present("gzip") {
+        builder = builder.compression(Compression::Gzip);
+    } else if matches.is_present("zstd") {
+        let level = matches
+            .value_of("zstd_level")
+            .unwrap_or("3")
+            .parse()
+            .expect("Invalid '--zstd-level', must be a number from 1 to 22.");
+        builder = builder.compression(Compression::Zstd(level));
     }
 
-    let elastic_dumper = created.unwrap();
+    let mut elastic_dumper = match builder.build() {
+        Ok(dumper) => dumper
+            .allow_partial(matches.is_present("allow_partial"))
+            .verify_ids(matches.is_present("verify_ids"))
+            .shard_routing(matches.is_present("shard_routing")),
+        Err(e) => {
+            println!("Unable to create Elasticsearch object: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(dir) = matches.value_of("work_dir") {
+        elastic_dumper = elastic_dumper.work_dir(dir);
+    }
+    let part_limits = PartLimits {
+        max_documents: matches.value_of("part_documents").map(|documents| {
+            documents
+                .parse()
+                .expect("Invalid '--part-documents', must be a number of documents.")
+        }),
+        max_bytes: matches.value_of("part_size").map(|size| {
+            parse_size(size)
+                .expect("Invalid '--part-size', must be a number of bytes, e.g. [512M].")
+        }),
+    };
+    elastic_dumper = elastic_dumper.part_limits(part_limits);
+    match matches.value_of("format") {
+        Some("jsonl") => {
+            elastic_dumper = elastic_dumper.format(OutputFormat::JsonLines {
+                id_key: matches.value_of("id_key").map(|key| key.to_string()),
+                index_key: matches.value_of("index_key").map(|key| key.to_string()),
+            });
+        }
+        Some("parquet") => {
+            let compression = match matches.value_of("parquet_compression") {
+                Some("zstd") => ParquetCompression::Zstd,
+                _ => ParquetCompression::Snappy,
+            };
+            let row_group_size = matches
+                .value_of("row_group_size")
+                .unwrap_or("100000")
+                .parse()
+                .expect("Invalid '--row-group-size', must be a number of rows.");
+            elastic_dumper = elastic_dumper.format(OutputFormat::Parquet {
+                compression,
+                row_group_size,
+            });
+        }
+        Some("avro") => {
+            let codec = match matches.value_of("avro_codec") {
+                Some("zstd") => AvroCodec::Zstd,
+                _ => AvroCodec::Deflate,
+            };
+            elastic_dumper = elastic_dumper.format(OutputFormat::Avro {
+                codec,
+                nullable: matches.is_present("avro_nullable"),
+            });
+        }
+        Some(format @ "csv") | Some(format @ "tsv") => {
+            let fields = matches
+                .value_of("fields")
+                .expect(
+                    "'--format csv' and 'tsv' need '--fields', e.g. [_id,@timestamp,user.name].",
+                )
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .collect();
+            let mut options = CsvOptions::new(fields, if format == "tsv" { '\t' } else { ',' });
+            options.header = !matches.is_present("no_header");
+            options.quoting = match matches.value_of("quote") {
+                Some("always") => Quoting::Always,
+                Some("non-numeric") => Quoting::NonNumeric,
+                Some("never") => Quoting::Never,
+                _ => Quoting::Necessary,
+            };
+            if let Some(null) = matches.value_of("null") {
+                options.null = null.to_string();
+            }
+            let separator = matches.value_of("separator").unwrap_or(";").to_string();
+            options.arrays = match matches.value_of("arrays") {
+                Some("first") => ArrayStyle::First,
+                Some("json") => ArrayStyle::Json,
+                _ => ArrayStyle::Join(separator.clone()),
+            };
+            if matches.value_of("objects") == Some("pairs") {
+                options.objects = ObjectStyle::Pairs(separator);
+            }
+            elastic_dumper = elastic_dumper.format(OutputFormat::Csv(options));
+        }
+        _ => {}
+    }
+    if let Some(by) = matches.value_of("partition_by") {
+        let field = matches.value_of("partition_field");
+        let partitioning = match (by, field) {
+            ("day", field) => Partitioning::Day(field.unwrap_or("@timestamp").to_string()),
+            ("hour", field) => Partitioning::Hour(field.unwrap_or("@timestamp").to_string()),
+            (_, Some(field)) => Partitioning::Field(field.to_string()),
+            (_, None) => {
+                println!("'--partition-by field' needs a '--partition-field'.");
+                process::exit(1);
+            }
+        };
+        elastic_dumper = elastic_dumper.partitioning(partitioning);
+    }
+    if let Some(query) = matches.value_of("query") {
+        match serde_json::from_str(query) {
+            Ok(query) => elastic_dumper = elastic_dumper.query(query),
+            Err(e) => {
+                println!("Invalid query supplied to '--query': {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    let fields = |name: &str| -> Vec<String> {
+        matches
+            .value_of(name)
+            .map(|fields| {
+                fields
+                    .split(',')
+                    .map(|field| field.trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    elastic_dumper = elastic_dumper.source_filter(SourceFilter {
+        includes: fields("source_includes"),
+        excludes: fields("source_excludes"),
+    });
+    if let Some(path) = matches.value_of("redact") {
+        // A trailing newline from an editor or echo isn't part of the key
+        let key = matches.value_of("hmac_key_file").map(|key_file| {
+            let mut key = std::fs::read(key_file).unwrap_or_else(|e| {
+                println!("Unable to read '--hmac-key-file {}': {}", key_file, e);
+                process::exit(1);
+            });
+            while key.last().is_some_and(|byte| byte.is_ascii_whitespace()) {
+                key.pop();
+            }
+            key
+        });
+        match Redactor::load(Path::new(path), key) {
+            Ok(redactor) => elastic_dumper = elastic_dumper.redactor(redactor),
+            Err(e) => {
+                println!("Unable to load '--redact {}': {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = matches.value_of("transform") {
+        match Pipeline::load(Path::new(path)) {
+            Ok(pipeline) => elastic_dumper = elastic_dumper.transforms(pipeline),
+            Err(e) => {
+                println!("Unable to load '--transform {}': {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = matches.value_of("script") {
+        let mut limits = ScriptLimits::default();
+        if let Some(timeout) = matches.value_of("script_timeout") {
+            limits.timeout = Duration::from_millis(
+                timeout
+                    .parse()
+                    .expect("Invalid '--script-timeout', must be a number of milliseconds."),
+            );
+        }
+        match Script::load(Path::new(path), limits) {
+            Ok(script) => elastic_dumper = elastic_dumper.script(script),
+            Err(e) => {
+                println!("Unable to load '--script {}': {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
 
-    // Run the CMD
-    let compression = matches.is_present("compression");
-    let dumped: Result<(), reqwest::Error>;
-    if compression && destination == "s3" {
-        println!("Writing compressed to S3.");
-        dumped = elastic_dumper
-            .crunch_index_to_s3(index.to_string(), slices)
-            .await;
-        if !dumped.is_ok() {
-            println!("Unable to dump elasticsearch index.");
+    // Find the rest of the cluster before planning anything
+    if matches.is_present("sniff") {
+        if let Err(e) = elastic_dumper.sniff().await {
+            println!("Unable to sniff Elasticsearch nodes: {}", e);
             process::exit(1);
+        }
+    }
+
+    // Plan the slices from the shard layout, otherwise the builder's concurrency is used
+    let planned: Option<i64> = if concurrency == "auto" {
+        let budget: u64 = matches
+            .value_of("max_concurrency")
+            .unwrap_or("16")
+            .parse()
+            .expect("Invalid '--max-concurrency', must be a number of slices.");
+        match elastic_dumper.plan_slices(index, budget).await {
+            Ok(plan) => Some(plan.slices),
+            Err(e) => {
+                println!("Unable to plan slices from the shard layout: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Stop cleanly on Ctrl-C or SIGTERM, clearing scroll contexts on the way out
+    let cancel = CancelToken::new();
+    elastic_dumper = elastic_dumper.cancel_token(cancel.clone());
+    tokio::spawn(cancel_on_signal(cancel, Signals::new()));
+
+    // Run the CMD
+    println!(
+        "Writing {} to {}.",
+        if matches.is_present("gzip") || matches.is_present("zstd") {
+            "compressed"
         } else {
+            "uncompressed"
+        },
+        destination
+    );
+    let dumped: Result<DumpReport, DumpError> = match from {
+        Some(from) => {
+            let source = open_source(from, &matches).unwrap_or_else(|e| {
+                println!("Unable to read '--from {}': {}", from, e);
+                process::exit(1);
+            });
+            elastic_dumper.dump_source(index.to_string(), source).await
+        }
+        None => match planned {
+            Some(slices) => elastic_dumper.dump_index(index.to_string(), slices).await,
+            None => elastic_dumper.dump(index.to_string()).await,
+        },
+    };
+
+    match dumped {
+        Ok(report) => {
+            print!("{}", report);
+            if !report.is_complete() {
+                // Partial results were allowed, but they're still not a success
+                println!("Completed with failed slices, output is incomplete.");
+                process::exit(2);
+            }
             println!("Successfully Completed.");
         }
-    } else if compression && destination == "disk" {
-        println!("Writing compressed to disk.");
-        dumped = elastic_dumper
-            .crunch_index_to_disk(index.to_string(), slices)
-            .await;
-        if !dumped.is_ok() {
-            println!("Unable to dump elasticsearch index.");
+        Err(DumpError::Cancelled(report)) => {
+            print!("{}", report);
+            println!("Cancelled, partial output removed.");
+            process::exit(EXIT_CANCELLED);
+        }
+        Err(e) => {
+            if let DumpError::Incomplete(report) | DumpError::Mismatch(report) = &e {
+                print!("{}", report);
+            }
+            println!("Unable to dump elasticsearch index: {}", e);
             process::exit(1);
-        } else {
-            println!("Successfully Completed.");
         }
-    } else if !compression && destination == "s3" {
-        println!("Writing uncompressed to S3.");
-        dumped = elastic_dumper
-            .dump_index_to_s3(index.to_string(), slices)
-            .await;
-        if !dumped.is_ok() {
-            println!("Unable to dump elasticsearch index.");
+    }
+}
+
+fn parse_size(size: &str) -> Option<u64> {
+    // Plain bytes, or with a binary K, M or G suffix
+    let size = size.trim().to_uppercase();
+    let (number, multiplier) = match size.chars().last()? {
+        'K' => (&size[..size.len() - 1], 1 << 10),
+        'M' => (&size[..size.len() - 1], 1 << 20),
+        'G' => (&size[..size.len() - 1], 1 << 30),
+        _ => (&size[..], 1),
+    };
+    number.parse::<u64>().ok().map(|number| number * multiplier)
+}
+
+async fn cancel_on_signal(cancel: CancelToken, mut signals: Signals) {
+    // The first signal asks the scrollers to stop, a second one doesn't wait for them
+    loop {
+        signals.next().await;
+        if cancel.is_cancelled() {
+            println!("Exiting without clearing scroll contexts.");
+            process::exit(EXIT_CANCELLED);
+        }
+        println!("Cancelling, clearing scroll contexts. Signal again to exit immediately.");
+        cancel.cancel();
+    }
+}
+
+// Registered once, so a signal arriving while the last one is handled isn't lost
+#[cfg(unix)]
+struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> Signals {
+        use tokio::signal::unix::{signal, SignalKind};
+        Signals {
+            interrupt: signal(SignalKind::interrupt()).expect("Unable to listen for SIGINT."),
+            terminate: signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM."),
+        }
+    }
+
+    async fn next(&mut self) {
+        tokio::select! {
+            _ = self.interrupt.recv() => {},
+            _ = self.terminate.recv() => {},
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> Signals {
+        Signals
+    }
+
+    async fn next(&mut self) {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+fn open_source(from: &str, matches: &ArgMatches) -> Result<Box<dyn Source>, DumpError> {
+    // A dump on disk or in S3, or whatever is piped in
+    let allow_partial = matches.is_present("allow_partial");
+    if from == "-" {
+        let stdin = BufReader::new(io::stdin());
+        return Ok(Box::new(NdjsonSource::new("stdin", stdin)));
+    }
+    match from.strip_prefix("s3:") {
+        Some(key) => {
+            let bucket_name = matches
+                .value_of("bucket")
+                .expect("No S3 bucket name provided. Use the '--bucket' argument");
+            let bucket = s3_bucket(bucket_name, matches);
+            Ok(Box::new(DumpSource::from_s3(&bucket, key, allow_partial)?))
+        }
+        None => Ok(Box::new(DumpSource::open(Path::new(from), allow_partial)?)),
+    }
+}
+
+fn s3_bucket(bucket_name: &str, matches: &ArgMatches) -> S3Bucket {
+    let s3_access_key = env::var("S3_ACCESS_KEY")
+        .expect("No AWS access key ID provided. Set the S3_ACCESS_KEY environment variable.");
+    let s3_secret_key = env::var("S3_SECRET_KEY")
+        .expect("No AWS secret access key provided. Set the S3_SECRET_KEY environment variable.");
+    let s3_region = matches
+        .value_of("region")
+        .expect("No AWS region supplied. Use the '--region' argument");
+    let s3creds = S3Credentials::new(Some(s3_access_key), Some(s3_secret_key), None, None);
+    let region = match s3_region.parse() {
+        Ok(region) => region,
+        Err(_) => {
+            println!("Unknown AWS region: {}", s3_region);
             process::exit(1);
+        }
+    };
+    S3Bucket::new(bucket_name, region, s3creds).expect("Unable to create S3 bucket option")
+}
+
+fn verify(matches: &ArgMatches) -> ! {
+    // No cluster needed, only the dump itself
+    let path = matches
+        .value_of("path")
+        .expect("No dump supplied to verify.");
+    let verified: Result<VerifyReport, DumpError> =
+        if let Some(bucket_name) = matches.value_of("bucket") {
+            dumper::verify_s3(&s3_bucket(bucket_name, matches), path)
+        } else if path.ends_with("manifest.json") {
+            dumper::verify_manifest(Path::new(path))
         } else {
-            println!("Successfully Completed.");
+            dumper::verify_dump(Path::new(path))
+        };
+
+    match verified {
+        Ok(report) => {
+            print!("{}", report);
+            if !report.is_ok() {
+                println!("Verification FAILED.");
+                process::exit(1);
+            }
+            println!("Verification passed.");
+            process::exit(0);
         }
-    } else if !compression && destination == "disk" {
-        println!("Writing uncompressed to disk.");
-        dumped = elastic_dumper
-            .dump_index_to_disk(index.to_string(), slices)
-            .await;
-        if !dumped.is_ok() {
-            println!("Unable to dump elasticsearch index.");
+        Err(e) => {
+            println!("Unable to verify dump: {}", e);
             process::exit(1);
-        } else {
-            println!("Successfully Completed.");
         }
     }
 }